@@ -0,0 +1,118 @@
+// Encoding of a direction into Ambisonics B-format in the AmbiX convention:
+// channels in ACN order, normalized to SN3D. See Nachbar et al., "AmbiX - A
+// Suggested Ambisonics Format" (2011). Up to third order, enough for any
+// dome we know of.
+
+pub const MAX_ORDER: u8 = 3;
+
+/// Number of channels of a full sphere encoding of `order`.
+pub fn channels(order: u8) -> u16 {
+    let n = order as u16 + 1;
+    n * n
+}
+
+/// Gains of the (order + 1)² channels for a sound from `azimuth` (counter
+/// clockwise from the front, so positive is left) and `elevation`, both in
+/// degrees.
+pub fn encode(order: u8, azimuth: f64, elevation: f64) -> Vec<f32> {
+    let (phi, theta) = (azimuth.to_radians(), elevation.to_radians());
+    let (sin_el, cos_el) = theta.sin_cos();
+    let sin_el2 = sin_el * sin_el;
+    let cos_el2 = cos_el * cos_el;
+    let sqrt3_2 = 3f64.sqrt() / 2.0;
+    let sqrt15_2 = 15f64.sqrt() / 2.0;
+    let sqrt5_8 = (5.0f64 / 8.0).sqrt();
+    let sqrt3_8 = (3.0f64 / 8.0).sqrt();
+
+    let all = [
+        // Order 0
+        1.0,
+        // Order 1
+        phi.sin() * cos_el,
+        sin_el,
+        phi.cos() * cos_el,
+        // Order 2
+        sqrt3_2 * (2.0 * phi).sin() * cos_el2,
+        sqrt3_2 * phi.sin() * (2.0 * theta).sin(),
+        0.5 * (3.0 * sin_el2 - 1.0),
+        sqrt3_2 * phi.cos() * (2.0 * theta).sin(),
+        sqrt3_2 * (2.0 * phi).cos() * cos_el2,
+        // Order 3
+        sqrt5_8 * (3.0 * phi).sin() * cos_el2 * cos_el,
+        sqrt15_2 * (2.0 * phi).sin() * sin_el * cos_el2,
+        sqrt3_8 * phi.sin() * cos_el * (5.0 * sin_el2 - 1.0),
+        0.5 * sin_el * (5.0 * sin_el2 - 3.0),
+        sqrt3_8 * phi.cos() * cos_el * (5.0 * sin_el2 - 1.0),
+        sqrt15_2 * (2.0 * phi).cos() * sin_el * cos_el2,
+        sqrt5_8 * (3.0 * phi).cos() * cos_el2 * cos_el,
+    ];
+    all[..channels(order.min(MAX_ORDER)) as usize].iter().map(|g| *g as f32).collect()
+}
+
+/// A sound from everywhere alike, only in the omnidirectional channel.
+pub fn diffuse(order: u8) -> Vec<f32> {
+    let mut gains = vec![0.0; channels(order) as usize];
+    gains[0] = 1.0;
+    gains
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_gains(gains: &[f32], expected: &[f64]) {
+        assert_eq!(gains.len(), expected.len());
+        for (gain, expected) in gains.iter().zip(expected) {
+            assert!(
+                (*gain as f64 - expected).abs() < 1e-6,
+                "{:?} instead of {:?}",
+                gains,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn acn_sn3d_coefficients() {
+        let sqrt3_2 = 3f64.sqrt() / 2.0;
+        let sqrt5_8 = (5.0f64 / 8.0).sqrt();
+        let sqrt3_8 = (3.0f64 / 8.0).sqrt();
+
+        // W Y Z X in ACN order
+        assert_gains(&encode(1, 0.0, 0.0), &[1.0, 0.0, 0.0, 1.0]);
+        assert_gains(&encode(1, 90.0, 0.0), &[1.0, 1.0, 0.0, 0.0]);
+        assert_gains(&encode(1, 0.0, 90.0), &[1.0, 0.0, 1.0, 0.0]);
+
+        #[rustfmt::skip]
+        let front = [
+            1.0,
+            0.0, 0.0, 1.0,
+            0.0, 0.0, -0.5, 0.0, sqrt3_2,
+            0.0, 0.0, 0.0, 0.0, -sqrt3_8, 0.0, sqrt5_8,
+        ];
+        assert_gains(&encode(3, 0.0, 0.0), &front);
+        #[rustfmt::skip]
+        let left = [
+            1.0,
+            1.0, 0.0, 0.0,
+            0.0, 0.0, -0.5, 0.0, -sqrt3_2,
+            -sqrt5_8, 0.0, -sqrt3_8, 0.0, 0.0, 0.0, 0.0,
+        ];
+        assert_gains(&encode(3, 90.0, 0.0), &left);
+        #[rustfmt::skip]
+        let up = [
+            1.0,
+            0.0, 1.0, 0.0,
+            0.0, 0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0,
+        ];
+        assert_gains(&encode(3, 0.0, 90.0), &up);
+    }
+
+    #[test]
+    fn orders_beyond_the_third_are_cut() {
+        assert_eq!(channels(2), 9);
+        assert_eq!(encode(2, 10.0, 20.0), encode(3, 10.0, 20.0)[..9]);
+        assert_eq!(encode(7, 10.0, 20.0).len(), 16);
+    }
+}
@@ -0,0 +1,220 @@
+// How sharply an event is localized, heard in the 44.00/44.22 Hz triangles:
+// a small credible area keeps them strong and clean, a large one detunes,
+// choruses and softens them ("Wenn unscharf").
+
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use rodio::source::Source;
+
+// Credible areas in deg² that sound fully focused and fully smeared. The
+// best localized events cover a few deg², single detector ones most of the sky.
+const SHARP_AREA_DEG2: f64 = 10.0;
+const SMEARED_AREA_DEG2: f64 = 10_000.0;
+
+// Settings of a fully smeared event
+const MAX_DETUNE_CENTS: f32 = 30.0;
+const MAX_CHORUS_MS: f32 = 12.0;
+const MIN_LEVEL: f32 = 0.3;
+
+// Shortest delay of the chorus lines and the rates they sweep at
+const CHORUS_BASE_MS: f32 = 15.0;
+const CHORUS_RATES_HZ: [f32; 2] = [0.23, 0.31];
+
+/// Timbre of the localization voices for one event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Blur {
+    /// Spread of the detuned copies to either side, in cents
+    pub detune_cents: f32,
+    /// Sweep of the chorus delay lines in milliseconds
+    pub chorus_ms: f32,
+    /// Factor to the volume of the voices
+    pub level: f32,
+}
+
+impl Default for Blur {
+    /// Focused, the voices as they are.
+    fn default() -> Self {
+        Blur { detune_cents: 0.0, chorus_ms: 0.0, level: 1.0 }
+    }
+}
+
+impl Blur {
+    /// Blur for the 90% credible area, on a logarithmic scale. Without an
+    /// area the voices stay as they are.
+    pub fn for_area(area_deg2: Option<f64>) -> Self {
+        let Some(area) = area_deg2.filter(|a| *a > 0.0) else {
+            return Blur::default();
+        };
+        let amount = ((area / SHARP_AREA_DEG2).log10()
+            / (SMEARED_AREA_DEG2 / SHARP_AREA_DEG2).log10())
+        .clamp(0.0, 1.0) as f32;
+        Blur {
+            detune_cents: amount * MAX_DETUNE_CENTS,
+            chorus_ms: amount * MAX_CHORUS_MS,
+            level: 1.0 - amount * (1.0 - MIN_LEVEL),
+        }
+    }
+
+    /// `source` with a copy detuned up and one down mixed in, and the chorus.
+    pub fn apply<S>(&self, source: S) -> Chorus<impl Source<Item = f32> + Clone>
+    where
+        S: Source<Item = f32> + Clone,
+    {
+        let ratio = 2f32.powf(self.detune_cents / 1200.0);
+        let copies = self.detune_cents / MAX_DETUNE_CENTS * 0.25;
+        let dry = 1.0 - 2.0 * copies;
+        // The copies drift out of phase, so keep the power rather than the sum
+        let gain = 1.0 / (dry * dry + 2.0 * copies * copies).sqrt();
+        let spread = source
+            .clone()
+            .amplify(dry * gain)
+            .mix(source.clone().speed(ratio).amplify(copies * gain))
+            .mix(source.speed(1.0 / ratio).amplify(copies * gain));
+        chorus(spread, self.chorus_ms)
+    }
+}
+
+/// Mixes `input` with copies on delay lines swept by `depth_ms`, the more the
+/// deeper. Without a depth it passes `input` through.
+pub fn chorus<S>(input: S, depth_ms: f32) -> Chorus<S>
+where
+    S: Source<Item = f32>,
+{
+    let channels = input.channels().max(1) as usize;
+    let rate = input.sample_rate() as f32;
+    let max_delay = ((CHORUS_BASE_MS + depth_ms) / 1000.0 * rate) as usize + 2;
+    Chorus {
+        channels,
+        rate,
+        depth_frames: depth_ms / 1000.0 * rate,
+        base_frames: CHORUS_BASE_MS / 1000.0 * rate,
+        wet: 0.5 * (depth_ms / MAX_CHORUS_MS).clamp(0.0, 1.0),
+        history: vec![0.0; max_delay * channels],
+        phases: [0.0, 0.25],
+        frame: 0,
+        channel: 0,
+        input,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Chorus<S> {
+    input: S,
+    channels: usize,
+    rate: f32,
+    depth_frames: f32,
+    base_frames: f32,
+    wet: f32,
+    // Past samples, interleaved, as a ring of frames
+    history: Vec<f32>,
+    // Of the sweep of each delay line, in turns
+    phases: [f32; 2],
+    frame: usize,
+    channel: usize,
+}
+
+impl<S> Chorus<S> {
+    // Sample of the current channel `delay` frames back, interpolated
+    fn delayed(&self, delay: f32) -> f32 {
+        let frames = self.history.len() / self.channels;
+        let whole = delay.floor() as usize;
+        let fraction = delay - whole as f32;
+        let at = |back: usize| {
+            let frame = (self.frame + frames - back.min(frames - 1)) % frames;
+            self.history[frame * self.channels + self.channel]
+        };
+        at(whole) * (1.0 - fraction) + at(whole + 1) * fraction
+    }
+}
+
+impl<S> Iterator for Chorus<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.input.next()?;
+        if self.wet == 0.0 {
+            return Some(sample);
+        }
+
+        let slot = self.frame * self.channels + self.channel;
+        self.history[slot] = sample;
+
+        let lines: f32 = self
+            .phases
+            .iter()
+            .map(|phase| {
+                let sweep = (2.0 * PI * phase).sin() * 0.5 + 0.5;
+                self.delayed(self.base_frames + self.depth_frames * sweep)
+            })
+            .sum();
+        let output = sample * (1.0 - self.wet) + lines / CHORUS_RATES_HZ.len() as f32 * self.wet;
+
+        self.channel += 1;
+        if self.channel == self.channels {
+            self.channel = 0;
+            self.frame = (self.frame + 1) % (self.history.len() / self.channels);
+            for (phase, hz) in self.phases.iter_mut().zip(CHORUS_RATES_HZ) {
+                *phase = (*phase + hz / self.rate).fract();
+            }
+        }
+        Some(output)
+    }
+}
+
+impl<S> Source for Chorus<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.rate as u32
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(area_deg2: f64) -> f32 {
+        Blur::for_area(Some(area_deg2)).detune_cents / MAX_DETUNE_CENTS
+    }
+
+    #[test]
+    fn blur_grows_with_the_area() {
+        assert_eq!(Blur::for_area(None), Blur::default());
+        assert_eq!(Blur::for_area(Some(0.0)), Blur::default());
+        assert_eq!(amount(1.0), 0.0);
+        assert_eq!(amount(SHARP_AREA_DEG2), 0.0);
+        assert_eq!(amount(SMEARED_AREA_DEG2), 1.0);
+        assert_eq!(amount(41_253.0), 1.0);
+        assert_eq!(
+            Blur::for_area(Some(41_253.0)),
+            Blur { detune_cents: MAX_DETUNE_CENTS, chorus_ms: MAX_CHORUS_MS, level: MIN_LEVEL }
+        );
+
+        // Halfway on the logarithmic scale
+        assert!((amount(316.227_77) - 0.5).abs() < 1e-6);
+        let mut last = Blur::for_area(Some(SHARP_AREA_DEG2));
+        for area in (11..10_000).step_by(7) {
+            let blur = Blur::for_area(Some(area as f64));
+            assert!(blur.detune_cents > last.detune_cents, "{:?} at {}", blur, area);
+            assert!(blur.chorus_ms > last.chorus_ms && blur.level < last.level);
+            last = blur;
+        }
+    }
+}
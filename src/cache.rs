@@ -0,0 +1,532 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::datafetch::{gracedb_date, GWEventVec, SKYMAP_SUFFIX};
+
+/// Name of the events cache inside the cache folder
+pub const EVENTS_CACHE: &str = "Events.json";
+
+/// Bump whenever the stored `GWEvent` changes in a way serde cannot absorb,
+/// and teach `migrate` how to read the old version.
+pub const CACHE_VERSION: u32 = 2;
+
+/// Where the events came from, so that a cache of another server or query
+/// is not mistaken for ours.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CacheSource {
+    pub url: String,
+    pub query: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CacheEnvelope {
+    pub version: u32,
+    #[serde(with = "gracedb_date")]
+    pub fetched_at: DateTime<Utc>,
+    pub source: CacheSource,
+    pub events: GWEventVec,
+}
+
+impl CacheEnvelope {
+    pub fn new(source: &CacheSource, events: GWEventVec) -> Self {
+        CacheEnvelope {
+            version: CACHE_VERSION,
+            fetched_at: Utc::now(),
+            source: source.clone(),
+            events,
+        }
+    }
+
+    pub fn age(&self) -> Option<Duration> {
+        // A fetch time in the future means the clock was changed; we cannot
+        // know the age then
+        (Utc::now() - self.fetched_at).to_std().ok()
+    }
+
+    pub fn is_fresh(&self, source: &CacheSource, duration: Duration) -> bool {
+        self.source == *source && self.age().is_some_and(|age| age < duration)
+    }
+}
+
+// Version 1 stored the distances and the area rounded to integers and
+// without units in the names
+fn migrate_v1_events(events: &mut serde_json::Value) {
+    let renamed =
+        [("far", "far_hz"), ("distance", "distance_mpc"), ("distance_std", "distance_std_mpc")];
+    for event in events.as_array_mut().into_iter().flatten() {
+        let Some(event) = event.as_object_mut() else {
+            continue;
+        };
+        for (old, new) in renamed {
+            if let Some(value) = event.remove(old) {
+                event.insert(new.to_string(), value);
+            }
+        }
+        // The area was never filled in, 0 meant unknown
+        if let Some(area) = event.remove("location_area") {
+            let area = area.as_f64().filter(|area| *area > 0.0);
+            event.insert("location_area_deg2".to_string(), json!(area));
+        }
+    }
+}
+
+// Older caches are brought up to date or rejected
+fn migrate(
+    mut value: serde_json::Value,
+    path: &Path,
+) -> Result<CacheEnvelope, Box<dyn std::error::Error>> {
+    // Before versioning the cache was a bare list of events. The file time is
+    // the best guess we have for when it was fetched.
+    if value.is_array() {
+        let modified: DateTime<Utc> = fs::metadata(path)?.modified()?.into();
+        value = json!({
+            "version": 1,
+            "fetched_at": modified.format("%+").to_string(),
+            "source": CacheSource { url: String::new(), query: String::new() },
+            "events": value,
+        });
+    }
+
+    if value.get("version").and_then(|v| v.as_u64()) == Some(1) {
+        migrate_v1_events(&mut value["events"]);
+        value["version"] = json!(2);
+    }
+
+    match value.get("version").and_then(|v| v.as_u64()) {
+        Some(v) if v == CACHE_VERSION as u64 => Ok(serde_json::from_value(value)?),
+        Some(v) => Err(format!("unsupported cache version {}", v).into()),
+        None => Err("not an event cache".into()),
+    }
+}
+
+pub fn read_cache(path: &Path) -> Result<CacheEnvelope, Box<dyn std::error::Error>> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let value: serde_json::Value = serde_json::from_reader(reader)?;
+    migrate(value, path)
+}
+
+pub fn write_to_cache(data: &CacheEnvelope, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    write_atomically(path, &serde_json::to_vec(data)?)?;
+    Ok(())
+}
+
+fn fetch_fn_to_cache<F>(
+    f: F,
+    path: &Path,
+    source: &CacheSource,
+) -> Result<GWEventVec, Box<dyn std::error::Error>>
+where
+    F: FnOnce() -> Result<GWEventVec, Box<dyn std::error::Error>>,
+{
+    let data = f()?;
+    write_to_cache(&CacheEnvelope::new(source, data.clone()), path)?;
+    Ok(data)
+}
+
+// Wait before fetching again after a failed renewal, doubled after every
+// further failure
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+/// Fetch of new events running in the background while the stale ones play.
+pub struct Revalidation {
+    stale_since: DateTime<Utc>,
+    result: Receiver<Result<GWEventVec, String>>,
+    path: PathBuf,
+    source: CacheSource,
+    failures: u32,
+}
+
+impl Revalidation {
+    /// How old the events being played are. `None` if the clock was changed.
+    pub fn age(&self) -> Option<Duration> {
+        (Utc::now() - self.stale_since).to_std().ok()
+    }
+
+    /// The new events, or why there are none, once the fetch is over.
+    pub fn poll(&self) -> Option<Result<GWEventVec, String>> {
+        self.result.try_recv().ok()
+    }
+
+    /// Blocks until the fetch is over.
+    pub fn wait(self) -> Result<GWEventVec, String> {
+        self.result.recv().unwrap_or_else(|e| Err(e.to_string()))
+    }
+
+    /// Fetches again after a failed fetch, waiting longer after every failure.
+    pub fn retry<F>(self, f: F) -> Revalidation
+    where
+        F: FnOnce() -> Result<GWEventVec, Box<dyn std::error::Error>> + Send + 'static,
+    {
+        let delay =
+            RETRY_DELAY.saturating_mul(2u32.saturating_pow(self.failures)).min(MAX_RETRY_DELAY);
+        spawn_renewal(None, delay, &self.path, &self.source, self.stale_since, self.failures + 1, f)
+    }
+}
+
+// Renews `path` in the background. Without a `lock`, it waits for the other
+// instances renewing it first.
+fn spawn_renewal<F>(
+    lock: Option<File>,
+    delay: Duration,
+    path: &Path,
+    source: &CacheSource,
+    stale_since: DateTime<Utc>,
+    failures: u32,
+    f: F,
+) -> Revalidation
+where
+    F: FnOnce() -> Result<GWEventVec, Box<dyn std::error::Error>> + Send + 'static,
+{
+    let (sender, result) = mpsc::channel();
+    let (thread_path, thread_source) = (path.to_path_buf(), source.clone());
+    thread::spawn(move || {
+        thread::sleep(delay);
+        let lock = match lock {
+            Some(lock) => Ok(lock),
+            None => open_lock(&lock_path(&thread_path))
+                .and_then(|lock| lock.lock_exclusive().map(|()| lock)),
+        };
+        let renewed = match lock {
+            // Held until the new events are written
+            Ok(_lock) => {
+                fetch_fn_to_cache(f, &thread_path, &thread_source).map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        let _ = sender.send(renewed);
+    });
+    Revalidation { stale_since, result, path: path.to_path_buf(), source: source.clone(), failures }
+}
+
+/// Returns the cached events while they are fresh and calls `f` for new ones
+/// otherwise. Stale events of the same source are returned right away, with
+/// `f` running in the background. Only one gwrust at a time renews the cache;
+/// the others keep playing what is there rather than waiting.
+pub fn read_or_renew_cache<F>(
+    path: &Path,
+    duration: Duration,
+    source: &CacheSource,
+    f: F,
+) -> Result<(GWEventVec, Option<Revalidation>), Box<dyn std::error::Error>>
+where
+    F: FnOnce() -> Result<GWEventVec, Box<dyn std::error::Error>> + Send + 'static,
+{
+    let lock = open_lock(&lock_path(path))?;
+    match lock.try_lock_exclusive() {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => match read_cache(path) {
+            Ok(cached) => {
+                println!("Another gwrust is renewing {:?}. Using the cached events.", path);
+                return Ok((cached.events, None));
+            }
+            // Nothing to play yet; the other one will have it soon
+            Err(_) => {
+                println!("Waiting for another gwrust to fetch the events.");
+                lock.lock_exclusive()?;
+            }
+        },
+        Err(e) => return Err(e.into()),
+    }
+
+    let maybe_cached_value = read_cache(path);
+
+    match maybe_cached_value {
+        Ok(cached) if cached.is_fresh(source, duration) => {
+            println!("Loading event data from cache file {:?}.", path);
+            Ok((cached.events, None))
+        }
+        Ok(cached) if cached.source == *source => {
+            println!("Cache file {:?} is stale. Renewing in the background.", path);
+            let stale_since = cached.fetched_at;
+            let revalidation =
+                spawn_renewal(Some(lock), Duration::ZERO, path, source, stale_since, 0, f);
+            Ok((cached.events, Some(revalidation)))
+        }
+        Ok(cached) => {
+            fetch_fn_to_cache(f, path, source).map(|events| (events, None)).or_else(|e| {
+                println!("Error updating cache: {:?}. Falling back to old version.", e);
+                Ok((cached.events, None))
+            })
+        }
+        Err(e) => {
+            if fs::metadata(path).is_ok() {
+                println!("Discarding cache file {:?}: {}", path, e);
+            }
+            fetch_fn_to_cache(f, path, source).map(|events| (events, None))
+        }
+    }
+}
+
+/// Folder shared by all gwrust instances of this user: `$XDG_CACHE_HOME/gwrust`,
+/// `~/.cache/gwrust` or `cache` in the current folder, whichever is known first.
+pub fn default_cache_dir() -> PathBuf {
+    let xdg = std::env::var_os("XDG_CACHE_HOME").filter(|dir| Path::new(dir).is_absolute());
+    let home = std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache"));
+    match xdg.map(PathBuf::from).or(home) {
+        Some(dir) => dir.join("gwrust"),
+        None => PathBuf::from("cache"),
+    }
+}
+
+const DIR_LOCK: &str = "cache.lock";
+
+// Where gwrust kept the skymaps, relative to the current folder, before there
+// was a shared cache folder. The events were kept next to it.
+const LEGACY_CACHE_DIR: &str = "cache";
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    // Across file systems only copying works
+    fs::rename(from, to).or_else(|_| {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    })
+}
+
+/// Moves the events and skymaps an older gwrust kept in the current folder
+/// into `dir`, so that an update does not start from an empty cache. Files
+/// already in `dir` are kept.
+pub fn migrate_legacy_cache(dir: &Path) -> io::Result<()> {
+    move_legacy_cache(Path::new(""), dir)
+}
+
+// Only what gwrust wrote there; the folder may hold anything else
+fn move_legacy_cache(from: &Path, dir: &Path) -> io::Result<()> {
+    let mut moves = vec![(from.join(EVENTS_CACHE), dir.join(EVENTS_CACHE))];
+    let legacy = from.join(LEGACY_CACHE_DIR);
+    let same_dir = fs::canonicalize(&legacy)
+        .ok()
+        .is_some_and(|legacy| fs::canonicalize(dir).is_ok_and(|dir| dir == legacy));
+    if legacy.is_dir() && !same_dir {
+        for entry in fs::read_dir(&legacy)? {
+            let entry = entry?;
+            let skymap = entry.file_name().to_string_lossy().ends_with(SKYMAP_SUFFIX);
+            if skymap && entry.file_type()?.is_file() {
+                moves.push((entry.path(), dir.join(entry.file_name())));
+            }
+        }
+    }
+
+    for (from, to) in moves {
+        if from.is_file() && !to.exists() {
+            fs::create_dir_all(dir)?;
+            move_file(&from, &to)?;
+            println!("Moved {:?} to {:?}.", from, to);
+        }
+    }
+    Ok(())
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    path.with_file_name(name)
+}
+
+pub fn is_lock_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "lock")
+}
+
+// Lock files are never removed, so that everybody locks the same file
+fn open_lock(lock: &Path) -> io::Result<File> {
+    if let Some(parent) = lock.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    File::options().create(true).truncate(false).write(true).open(lock)
+}
+
+/// Held while files in the cache folder are being written or read, so that
+/// no other instance evicts them in the meantime. Released on drop.
+pub fn lock_cache_dir(dir: &Path) -> io::Result<File> {
+    let lock = open_lock(&dir.join(DIR_LOCK))?;
+    FileExt::lock_shared(&lock)?;
+    Ok(lock)
+}
+
+/// The exclusive counterpart of `lock_cache_dir`, or `None` while any other
+/// instance is using the cache folder.
+pub fn try_lock_cache_dir_exclusive(dir: &Path) -> io::Result<Option<File>> {
+    let lock = open_lock(&dir.join(DIR_LOCK))?;
+    match lock.try_lock_exclusive() {
+        Ok(()) => Ok(Some(lock)),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Name `path` is written under before it is complete. Unique per process, so
+/// instances writing the same file do not get in each other's way.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.part", std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Replaces `path` in one step: readers see either the old or the new
+/// contents, never a partly written file.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    replace_file(path, contents, true)
+}
+
+/// Like `write_atomically`, without waiting for the disk. For files rewritten
+/// so often that losing the last version in a crash does not matter.
+pub fn write_atomically_unsynced(path: &Path, contents: &[u8]) -> io::Result<()> {
+    replace_file(path, contents, false)
+}
+
+fn replace_file(path: &Path, contents: &[u8], sync: bool) -> io::Result<()> {
+    let part = part_path(path);
+    let written = File::create(&part).and_then(|mut file| {
+        file.write_all(contents)?;
+        if sync {
+            file.sync_all()?;
+        }
+        Ok(())
+    });
+    match written.and_then(|_| fs::rename(&part, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&part);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datafetch::GWEvent;
+
+    // An event as version 1 stored it
+    fn v1_event() -> serde_json::Value {
+        let mut event = serde_json::to_value(GWEvent {
+            far_hz: 1e-9,
+            distance_mpc: 440.0,
+            distance_std_mpc: 170.0,
+            ..GWEvent::blank("S1", Utc::now())
+        })
+        .unwrap();
+        let event = event.as_object_mut().unwrap();
+        for (old, new) in
+            [("far", "far_hz"), ("distance", "distance_mpc"), ("distance_std", "distance_std_mpc")]
+        {
+            let value = event.remove(new).unwrap();
+            event.insert(old.to_string(), value);
+        }
+        event.remove("location_area_deg2");
+        event.insert("location_area".to_string(), json!(0));
+        json!(event)
+    }
+
+    fn assert_migrated(cached: &CacheEnvelope) {
+        assert_eq!(cached.version, CACHE_VERSION);
+        let event = &cached.events[0];
+        assert_eq!(event.id, "S1");
+        assert_eq!(event.far_hz, 1e-9);
+        assert_eq!(event.distance_mpc, 440.0);
+        assert_eq!(event.distance_std_mpc, 170.0);
+        assert_eq!(event.location_area_deg2, None);
+    }
+
+    #[test]
+    fn migrates_older_caches() {
+        let dir = std::env::temp_dir().join(format!("gwrust-migrate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(EVENTS_CACHE);
+
+        // A bare list counts as fetched when the file was written, from
+        // nowhere in particular
+        let modified = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().to_utc();
+        fs::write(&path, "[]").unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(modified.into()).unwrap();
+        let cached = migrate(json!([v1_event()]), &path).unwrap();
+        assert_migrated(&cached);
+        assert_eq!(cached.fetched_at, modified);
+        assert_eq!(cached.source, CacheSource { url: String::new(), query: String::new() });
+
+        let source = CacheSource { url: "https://gracedb".to_string(), query: "q".to_string() };
+        let v1 = json!({
+            "version": 1,
+            "fetched_at": "2025-01-02T03:04:05+00:00",
+            "source": source,
+            "events": [v1_event()],
+        });
+        let cached = migrate(v1, &path).unwrap();
+        assert_migrated(&cached);
+        assert_eq!(cached.fetched_at.to_rfc3339(), "2025-01-02T03:04:05+00:00");
+        assert_eq!(cached.source, source);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn moves_only_events_and_skymaps() {
+        let root = std::env::temp_dir().join(format!("gwrust-legacy-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.join("shared");
+        fs::create_dir_all(root.join(LEGACY_CACHE_DIR).join("old")).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        let legacy = [
+            EVENTS_CACHE,
+            "cache/S1-bayestar.multiorder.fits",
+            "cache/S2-bayestar.multiorder.fits",
+            "cache/S1-bayestar.multiorder.fits.lock",
+            "cache/notes.txt",
+            "cache/old/S3-bayestar.multiorder.fits",
+        ];
+        for name in legacy {
+            fs::write(root.join(name), name).unwrap();
+        }
+        // Newer than the legacy one
+        fs::write(dir.join("S2-bayestar.multiorder.fits"), "new").unwrap();
+
+        move_legacy_cache(&root, &dir).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join(EVENTS_CACHE)).unwrap(), EVENTS_CACHE);
+        assert!(dir.join("S1-bayestar.multiorder.fits").exists());
+        assert_eq!(fs::read_to_string(dir.join("S2-bayestar.multiorder.fits")).unwrap(), "new");
+        let left: Vec<&str> = legacy.into_iter().filter(|name| root.join(name).exists()).collect();
+        assert_eq!(
+            left,
+            [
+                "cache/S2-bayestar.multiorder.fits",
+                "cache/S1-bayestar.multiorder.fits.lock",
+                "cache/notes.txt",
+                "cache/old/S3-bayestar.multiorder.fits",
+            ]
+        );
+        let mut moved: Vec<_> =
+            fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        moved.sort();
+        assert_eq!(
+            moved,
+            [EVENTS_CACHE, "S1-bayestar.multiorder.fits", "S2-bayestar.multiorder.fits"]
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let path = Path::new(EVENTS_CACHE);
+        // Even one that would parse as the current version
+        let mut future = serde_json::to_value(CacheEnvelope::new(
+            &CacheSource { url: String::new(), query: String::new() },
+            vec![GWEvent::blank("S1", Utc::now())],
+        ))
+        .unwrap();
+        future["version"] = json!(CACHE_VERSION + 1);
+        let error = migrate(future, path).err().unwrap();
+        assert_eq!(error.to_string(), format!("unsupported cache version {}", CACHE_VERSION + 1));
+        assert!(migrate(json!({ "events": [] }), path).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::datafetch::{GWEvent, GWEventVec};
+use crate::physics::{NORMAL_90, SECONDS_PER_YEAR};
+
+/// One row of a GWTC catalog export as downloaded from GWOSC
+/// (https://gwosc.org/eventapi/csv/GWTC/). Columns we do not use are ignored
+/// and empty cells become `None`.
+#[derive(Debug, Deserialize, Clone)]
+struct GwtcRow {
+    #[serde(rename = "commonName")]
+    common_name: String,
+    #[serde(rename = "GPS")]
+    gps: f64,
+    mass_1_source: Option<f64>,
+    mass_2_source: Option<f64>,
+    network_matched_filter_snr: Option<f64>,
+    luminosity_distance: Option<f64>,
+    luminosity_distance_lower: Option<f64>,
+    luminosity_distance_upper: Option<f64>,
+    chirp_mass_source: Option<f64>,
+    chirp_mass: Option<f64>,
+    far: Option<f64>,
+    p_astro: Option<f64>,
+}
+
+// Heavier than any known neutron star
+const MAX_NS_MASS: f64 = 3.0;
+// Lighter than any known black hole. In between is the mass gap.
+const MIN_BH_MASS: f64 = 5.0;
+
+// Dates (as UTC unix timestamps) from which the given number of leap seconds
+// separates GPS time from UTC
+const GPS_LEAP_SECONDS: [(i64, i64); 18] = [
+    (362793600, 1),   // 1981-07-01
+    (394329600, 2),   // 1982-07-01
+    (425865600, 3),   // 1983-07-01
+    (489024000, 4),   // 1985-07-01
+    (567993600, 5),   // 1988-01-01
+    (631152000, 6),   // 1990-01-01
+    (662688000, 7),   // 1991-01-01
+    (709948800, 8),   // 1992-07-01
+    (741484800, 9),   // 1993-07-01
+    (773020800, 10),  // 1994-07-01
+    (820454400, 11),  // 1996-01-01
+    (867715200, 12),  // 1997-07-01
+    (915148800, 13),  // 1999-01-01
+    (1136073600, 14), // 2006-01-01
+    (1230768000, 15), // 2009-01-01
+    (1341100800, 16), // 2012-07-01
+    (1435708800, 17), // 2015-07-01
+    (1483228800, 18), // 2017-01-01
+];
+
+// 1980-01-06T00:00:00Z
+const GPS_EPOCH: i64 = 315964800;
+
+pub fn gps_to_utc(gps: f64) -> DateTime<Utc> {
+    // At ~1e9 s an f64 resolves only about a microsecond, milliseconds are
+    // all the catalog gives anyway
+    let naive_unix = GPS_EPOCH + gps.trunc() as i64;
+    let nanos = (gps.fract() * 1e3).round() as u32 * 1_000_000;
+    let leap_seconds = GPS_LEAP_SECONDS
+        .iter()
+        .take_while(|(since, _)| *since <= naive_unix)
+        .last()
+        .map_or(0, |(_, n)| *n);
+    Utc.timestamp_opt(naive_unix - leap_seconds, nanos.min(999_999_999))
+        .single()
+        .unwrap_or_default()
+}
+
+fn gwtc_to_gwevent(row: GwtcRow) -> GWEvent {
+    // The catalog has no per-class probabilities, only p_astro. We assign it
+    // to the class the component masses point to.
+    let p_astro = row.p_astro.unwrap_or(1.0).clamp(0.0, 1.0);
+    let is_ns = |m: Option<f64>| m.is_some_and(|m| m < MAX_NS_MASS);
+    let (ns_ns, ns_bh, bh_bh) = match (is_ns(row.mass_1_source), is_ns(row.mass_2_source)) {
+        (true, true) => (p_astro, 0.0, 0.0),
+        (false, true) => (0.0, p_astro, 0.0),
+        _ => (0.0, 0.0, p_astro),
+    };
+    let in_mass_gap = |m: Option<f64>| m.is_some_and(|m| (MAX_NS_MASS..MIN_BH_MASS).contains(&m));
+    let mass_gap = if in_mass_gap(row.mass_1_source) || in_mass_gap(row.mass_2_source) {
+        p_astro
+    } else {
+        0.0
+    };
+
+    // Catalog bounds are offsets to the median that enclose 90%
+    let distance = row.luminosity_distance.unwrap_or(0.0);
+    let lower = row.luminosity_distance_lower.map(|offset| distance - offset.abs());
+    let upper = row.luminosity_distance_upper.map(|offset| distance + offset.abs());
+    let distance_std = match (lower, upper) {
+        (Some(lower), Some(upper)) => (upper - lower) / 2.0 / NORMAL_90,
+        _ => 0.0,
+    };
+
+    GWEvent {
+        id: row.common_name,
+        time: gps_to_utc(row.gps),
+        // GWTC gives the FAR per year, GraceDB in Hz
+        far_hz: row.far.map_or(0.0, |far| far / SECONDS_PER_YEAR),
+        location_area_deg2: None,
+        sky_position: None,
+        distance_mpc: distance,
+        distance_std_mpc: distance_std,
+        distance_lower_mpc: lower,
+        distance_upper_mpc: upper,
+        detectors: Vec::new(),
+        ns_ns,
+        ns_bh,
+        bh_bh,
+        terrestrial: 1.0 - p_astro,
+        mass_gap,
+        has_ns: ns_ns + ns_bh,
+        // Would need the spins and the equation of state
+        has_remnant: 0.0,
+        // Everything in the catalog passed its significance threshold
+        significant: true,
+        group: "CBC".to_string(),
+        pipeline: String::new(),
+        search: String::new(),
+        chirp_mass: row.chirp_mass_source.or(row.chirp_mass),
+        network_snr: row.network_matched_filter_snr,
+    }
+}
+
+/// Reads a GWTC-1/2/3 CSV export into events, sorted by time.
+pub fn read_catalog(path: &Path) -> Result<GWEventVec, Box<dyn std::error::Error>> {
+    let mut reader = csv::Reader::from_path(path)?;
+
+    let mut result: GWEventVec = Vec::new();
+    for row in reader.deserialize() {
+        let row: GwtcRow = row?;
+        result.push(gwtc_to_gwevent(row));
+    }
+    result.sort_by_key(|ev| ev.time);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(csv: &str) -> Vec<GwtcRow> {
+        csv::Reader::from_reader(csv.as_bytes()).deserialize().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn gps_times() {
+        // GW150914, 17 leap seconds after GPS started
+        let time = gps_to_utc(1126259462.4);
+        assert_eq!(time.to_rfc3339(), "2015-09-14T09:50:45.400+00:00");
+        assert_eq!(gps_to_utc(0.0).timestamp(), GPS_EPOCH);
+    }
+
+    #[test]
+    fn rows_with_empty_cells() {
+        let rows = rows(
+            "commonName,GPS,mass_1_source,mass_2_source,network_matched_filter_snr,luminosity_distance,luminosity_distance_lower,luminosity_distance_upper,chirp_mass_source,chirp_mass,far,p_astro,redshift\n\
+             GW150914,1126259462.4,35.6,30.6,24.4,440,-170,150,28.6,,1e-07,1.0,0.09\n\
+             GW170817,1187008882.4,1.46,1.27,33.0,40,-15,7,,1.186,,,\n",
+        );
+        let events: Vec<GWEvent> = rows.into_iter().map(gwtc_to_gwevent).collect();
+
+        let bbh = &events[0];
+        assert_eq!(bbh.id, "GW150914");
+        assert_eq!((bbh.bh_bh, bbh.ns_ns, bbh.terrestrial), (1.0, 0.0, 0.0));
+        assert_eq!(bbh.distance_mpc, 440.0);
+        assert_eq!((bbh.distance_lower_mpc, bbh.distance_upper_mpc), (Some(270.0), Some(590.0)));
+        assert_eq!(bbh.chirp_mass, Some(28.6));
+        assert!((bbh.far_hz * SECONDS_PER_YEAR - 1e-7).abs() < 1e-20);
+
+        // Empty cells: no FAR, p_astro taken as 1, detector frame chirp mass
+        let bns = &events[1];
+        assert_eq!((bns.ns_ns, bns.has_ns), (1.0, 1.0));
+        assert_eq!(bns.far_hz, 0.0);
+        assert_eq!(bns.chirp_mass, Some(1.186));
+        assert_eq!(bns.network_snr, Some(33.0));
+    }
+}
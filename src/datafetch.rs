@@ -1,20 +1,112 @@
 use std::collections::HashMap;
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use chrono::{DateTime, Utc};
-use fitrs::Fits;
-use fitrs::HeaderValue::{CharacterString, RealFloatingNumber};
-use reqwest::blocking::Client;
-use reqwest::header::{ACCEPT, USER_AGENT};
+use rand::Rng;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{
+    HeaderMap, ACCEPT, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER,
+    USER_AGENT,
+};
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+use crate::observer::{HorizontalPosition, Site};
+use crate::physics::{self, NORMAL_90, SECONDS_PER_YEAR};
+use crate::skymap::{SkyPosition, Skymap};
+use crate::{cache, fits, fixtures};
+
+#[derive(Debug)]
+pub enum FetchError {
+    Network(reqwest::Error),
+    Status { status: u16, url: String },
+    InvalidUrl(String),
+    Json(serde_json::Error),
+    Fits { path: PathBuf, reason: String },
+    Incomplete { url: String, expected: u64, received: u64 },
+    Cache(std::io::Error),
+    // Reading a --replay-http or writing a --record-http folder
+    Fixture { path: PathBuf, error: std::io::Error },
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FetchError::Network(e) => write!(f, "network error: {}", e),
+            FetchError::Status { status, url } => write!(f, "HTTP status {} for {}", status, url),
+            FetchError::InvalidUrl(url) => write!(f, "invalid URL {}", url),
+            FetchError::Json(e) => write!(f, "could not parse JSON: {}", e),
+            FetchError::Fits { path, reason } => write!(f, "could not read {:?}: {}", path, reason),
+            FetchError::Incomplete { url, expected, received } => {
+                write!(f, "got {} of {} bytes from {}", received, expected, url)
+            }
+            FetchError::Cache(e) => write!(f, "cache I/O error: {}", e),
+            FetchError::Fixture { path, error } => write!(f, "fixture {:?}: {}", path, error),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FetchError::Network(e) => Some(e),
+            FetchError::Json(e) => Some(e),
+            FetchError::Cache(e) => Some(e),
+            FetchError::Fixture { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> Self {
+        FetchError::Network(e)
+    }
+}
+
+impl From<serde_json::Error> for FetchError {
+    fn from(e: serde_json::Error) -> Self {
+        FetchError::Json(e)
+    }
+}
+
+impl From<std::io::Error> for FetchError {
+    fn from(e: std::io::Error) -> Self {
+        FetchError::Cache(e)
+    }
+}
+
+/// The events that could be fetched, and what went wrong for the others.
+/// An event with a broken skymap is kept, but its failure is reported as well.
+#[derive(Debug)]
+pub struct FetchReport {
+    pub events: GWEventVec,
+    pub failures: Vec<(String, FetchError)>,
+}
+
+// Start of a response for the log, without cutting a character in half
+fn preview(text: &str) -> &str {
+    match text.char_indices().nth(60) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Clone)]
 pub struct GraceDbList {
     #[serde(rename = "numRows")]
     num_rows: u64,
     superevents: Vec<GraceDbListEvent>,
+    #[serde(default)]
+    links: HashMap<String, Option<String>>,
 }
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Clone)]
@@ -72,13 +164,13 @@ pub struct GraceDbEventClassification {
     terrestrial: f64,
 }
 
-mod gracedb_date {
+pub mod gracedb_date {
     use chrono::{DateTime, NaiveDateTime, Utc};
     use serde::{self, Deserialize, Deserializer, Serializer};
 
     // Events have one of these formats
-    const FORMAT_A: &'static str = "%+"; // matches %Y-%m-%dT%H:%M:%SZ";
-    const FORMAT_B: &'static str = "%Y-%m-%d %H:%M:%S %Z";
+    const FORMAT_A: &str = "%+"; // matches %Y-%m-%dT%H:%M:%SZ";
+    const FORMAT_B: &str = "%Y-%m-%d %H:%M:%S %Z";
 
     pub fn serialize<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -103,216 +195,1018 @@ mod gracedb_date {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GWEvent {
-    id: String,
+    pub id: String,
 
     #[serde(with = "gracedb_date")]
-    time: DateTime<Utc>,
+    pub time: DateTime<Utc>,
 
-    far: f64,
-    location_area: u64,
-    distance: u64,
-    distance_std: u64,
-    detectors: Vec<String>,
-    ns_ns: f64,
-    ns_bh: f64,
-    bh_bh: f64,
-    terrestrial: f64,
-    mass_gap: f64,
+    // False alarm rate in Hz
+    pub far_hz: f64,
+    // Area of the 90% credible region in deg², unknown without skymap
+    pub location_area_deg2: Option<f64>,
+    // Most probable direction, unknown without skymap
+    #[serde(default)]
+    pub sky_position: Option<SkyPosition>,
+    // Luminosity distance in Mpc, 0 if unknown. The mean for alerts, the
+    // median for catalog events.
+    pub distance_mpc: f64,
+    pub distance_std_mpc: f64,
+    // 90% credible interval of the distance in Mpc, where published
+    #[serde(default)]
+    pub distance_lower_mpc: Option<f64>,
+    #[serde(default)]
+    pub distance_upper_mpc: Option<f64>,
+    pub detectors: Vec<String>,
+    pub ns_ns: f64,
+    pub ns_bh: f64,
+    pub bh_bh: f64,
+    pub terrestrial: f64,
+    pub mass_gap: f64,
+    // Probabilities from em-bright, like mass_gap
+    #[serde(default)]
+    pub has_ns: f64,
+    #[serde(default)]
+    pub has_remnant: f64,
+
+    #[serde(default)]
+    pub significant: bool,
+    #[serde(default)]
+    pub group: String,
+    #[serde(default)]
+    pub pipeline: String,
+    #[serde(default)]
+    pub search: String,
+
+    // Only known for catalog events, GraceDB does not publish them for alerts
+    #[serde(default)]
+    pub chirp_mass: Option<f64>,
+    #[serde(default)]
+    pub network_snr: Option<f64>,
+}
+
+impl GWEvent {
+    /// Redshift for the distance, in Planck 2015 cosmology.
+    pub fn redshift(&self) -> f64 {
+        physics::redshift(self.distance_mpc)
+    }
+
+    /// Where the most probable direction is seen from `site` at `time`.
+    pub fn horizontal(&self, site: &Site, time: DateTime<Utc>) -> Option<HorizontalPosition> {
+        self.sky_position.map(|position| site.horizontal(position, time))
+    }
+
+    /// False alarms per year.
+    pub fn far_per_year(&self) -> f64 {
+        self.far_hz * SECONDS_PER_YEAR
+    }
+
+    /// Distance in Mpc below which the source lies with probability `p`.
+    /// Follows the 90% interval where it is published, the normal
+    /// distribution of mean and standard deviation otherwise.
+    pub fn distance_percentile_mpc(&self, p: f64) -> f64 {
+        let z = physics::normal_quantile(p);
+        let sigma = match (self.distance_lower_mpc, self.distance_upper_mpc) {
+            // Skewed distributions get a different width on either side
+            (Some(lower), _) if z < 0.0 => (self.distance_mpc - lower) / NORMAL_90,
+            (_, Some(upper)) if z >= 0.0 => (upper - self.distance_mpc) / NORMAL_90,
+            _ => self.distance_std_mpc,
+        };
+        (self.distance_mpc + z * sigma).max(0.0)
+    }
+}
+
+#[cfg(test)]
+impl GWEvent {
+    /// An event at `time` with nothing known about it but its id.
+    pub fn blank(id: &str, time: DateTime<Utc>) -> GWEvent {
+        GWEvent {
+            id: id.to_string(),
+            time,
+            far_hz: 0.0,
+            location_area_deg2: None,
+            sky_position: None,
+            distance_mpc: 0.0,
+            distance_std_mpc: 0.0,
+            distance_lower_mpc: None,
+            distance_upper_mpc: None,
+            detectors: Vec::new(),
+            ns_ns: 0.0,
+            ns_bh: 0.0,
+            bh_bh: 0.0,
+            terrestrial: 0.0,
+            mass_gap: 0.0,
+            has_ns: 0.0,
+            has_remnant: 0.0,
+            significant: true,
+            group: "CBC".to_string(),
+            pipeline: String::new(),
+            search: String::new(),
+            chirp_mass: None,
+            network_snr: None,
+        }
+    }
+}
+
+// Three significant digits, without switching to exponents
+fn precise(value: f64) -> String {
+    let decimals = if value.abs() < 1e-9 { 0 } else { 2 - value.abs().log10().floor() as i32 };
+    format!("{:.*}", decimals.max(0) as usize, value)
 }
 
 impl std::fmt::Display for GWEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Event: id={:<10} {:<8} time={} FAR={:<7.1e} area={} dist={:<4}±{:<4} ns_ns={:.3} ns_bh={:.3} bh_bh={:.3} terr={:.3} mass_gap={:.3}",
+        write!(
+            f,
+            "Event: id={:<10} {:<8} time={} FAR={:.1e} Hz",
             self.id,
             self.detectors.join(","),
             self.time,
-            self.far,
-            self.location_area,
-            self.distance,
-            self.distance_std,
+            self.far_hz
+        )?;
+        if self.far_hz > 0.0 {
+            write!(f, " (1 per {:.1e} yr)", 1.0 / self.far_per_year())?;
+        }
+        if let Some(area) = self.location_area_deg2 {
+            write!(f, " area={} deg²", precise(area))?;
+        }
+        if let Some(position) = self.sky_position {
+            write!(f, " {}", position)?;
+        }
+        if self.distance_mpc > 0.0 {
+            write!(
+                f,
+                " dist={}±{} Mpc",
+                precise(self.distance_mpc),
+                precise(self.distance_std_mpc)
+            )?;
+            let (lower, upper) =
+                (self.distance_percentile_mpc(0.05), self.distance_percentile_mpc(0.95));
+            write!(f, " (90%: {}–{})", precise(lower), precise(upper))?;
+            write!(f, " z={:.3}", self.redshift())?;
+        }
+        write!(f, " ns_ns={:.3} ns_bh={:.3} bh_bh={:.3} terr={:.3} mass_gap={:.3} has_ns={:.3} has_remnant={:.3}",
             self.ns_ns,
             self.ns_bh,
             self.bh_bh,
             self.terrestrial,
-            self.mass_gap
-        )
+            self.mass_gap,
+            self.has_ns,
+            self.has_remnant
+        )?;
+        if !self.pipeline.is_empty() {
+            write!(f, " {}/{}/{}", self.group, self.pipeline, self.search)?;
+        }
+        if self.significant {
+            write!(f, " significant")?;
+        }
+        if let Some(chirp_mass) = self.chirp_mass {
+            write!(f, " mchirp={:.2} Msun", chirp_mass)?;
+        }
+        if let Some(snr) = self.network_snr {
+            write!(f, " snr={:.1}", snr)?;
+        }
+        Ok(())
     }
 }
 
 pub type GWEventVec = Vec<GWEvent>;
 
-fn gracedb_to_gwevent(gracedb_event: GraceDbEvent, fits_data: Option<FitsParams>) -> GWEvent {
+pub fn gracedb_to_gwevent(gracedb_event: GraceDbEvent, fits_data: Option<FitsParams>) -> GWEvent {
     GWEvent {
         id: gracedb_event.superevent_id.clone(),
         time: gracedb_event.event.time,
-        far: gracedb_event.event.far,
-        location_area: 0, // TODO
-        distance: fits_data.as_ref().map_or(0, |d| d.dist_mean as u64),
-        distance_std: fits_data.as_ref().map_or(0, |d| d.dist_std as u64),
-        detectors: gracedb_event.event.instruments.clone(),
+        far_hz: gracedb_event.event.far,
+        location_area_deg2: fits_data.as_ref().and_then(|d| d.area_90_deg2),
+        sky_position: fits_data.as_ref().and_then(|d| d.max_probability),
+        distance_mpc: fits_data.as_ref().map_or(0.0, |d| d.dist_mean),
+        distance_std_mpc: fits_data.as_ref().map_or(0.0, |d| d.dist_std),
+        distance_lower_mpc: None,
+        distance_upper_mpc: None,
+        detectors: gracedb_event.event.instruments,
         ns_ns: gracedb_event.event.classification.bns,
         ns_bh: gracedb_event.event.classification.ns_bh,
         bh_bh: gracedb_event.event.classification.bbh,
         terrestrial: gracedb_event.event.classification.terrestrial,
-        mass_gap: 0.0, // TODO
+        mass_gap: gracedb_event.event.properties.has_mass_gap,
+        has_ns: gracedb_event.event.properties.has_ns,
+        has_remnant: gracedb_event.event.properties.has_remnant,
+        significant: gracedb_event.event.significant,
+        group: gracedb_event.event.group,
+        pipeline: gracedb_event.event.pipeline,
+        search: gracedb_event.event.search,
+        chirp_mass: None,
+        network_snr: None,
     }
 }
 
-fn read_gracedbevent(
-    url: &String,
-    client: &reqwest::blocking::Client,
-) -> Result<GraceDbEvent, Box<dyn std::error::Error>> {
-    let res = client
-        .get(url)
-        .header(USER_AGENT, "gwrust")
-        .header(ACCEPT, "application/json")
-        .send()?
-        .error_for_status()?;
+/// Where HTTP responses come from. Recorded responses are stored in the
+/// fixture layout that gwrust-mockdb serves.
+#[derive(Debug, Clone)]
+pub enum HttpMode {
+    Live,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+/// How hard we try before giving up on a request, and how much we allow
+/// ourselves to ask of GraceDB.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub timeout: Duration,
+    // Requests can start at once up to `burst`, after which one more is
+    // allowed every `min_interval`
+    pub min_interval: Duration,
+    pub burst: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            timeout: Duration::from_secs(30),
+            min_interval: Duration::from_millis(500),
+            burst: 100,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter: a random delay between half and the
+    /// full backoff, so that several installations do not retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay =
+            self.base_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+fn retry_after(res: &Response) -> Option<Duration> {
+    parse_retry_after(res.headers().get(RETRY_AFTER)?.to_str().ok()?, Utc::now())
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&Utc) - now).to_std().ok()
+}
+
+// Start of the next request for a token bucket that is full at `full`. Each
+// request pushes that time back by `min_interval`, and a request has to wait
+// while it is more than `burst` intervals ahead.
+fn take_token(full: &mut Instant, now: Instant, retry: &RetryPolicy) -> Instant {
+    let empty = (*full).max(now) + retry.min_interval;
+    let window = retry.min_interval.saturating_mul(retry.burst.max(1));
+    *full = empty;
+    empty.checked_sub(window).map_or(now, |start| start.max(now))
+}
+
+pub struct GraceDbClient {
+    client: Client,
+    base_url: String,
+    http_mode: HttpMode,
+    retry: RetryPolicy,
+    // When the token bucket of the rate limit is full again
+    bucket_full: Mutex<Instant>,
+    // Bodies and validators of earlier responses for conditional requests
+    http_cache: Option<PathBuf>,
+    cache_dir: PathBuf,
+}
+
+impl GraceDbClient {
+    pub fn new(
+        base_url: &str,
+        http_mode: HttpMode,
+        retry: RetryPolicy,
+        cache_dir: &Path,
+    ) -> Result<Self, FetchError> {
+        let client = Client::builder().timeout(retry.timeout).build()?;
+        Ok(GraceDbClient {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http_mode,
+            retry,
+            bucket_full: Mutex::new(Instant::now()),
+            http_cache: Some(cache_dir.join("http")),
+            cache_dir: cache_dir.to_path_buf(),
+        })
+    }
+
+    pub fn superevents_url(&self) -> String {
+        format!("{}/superevents/", self.base_url)
+    }
+
+    // Rate limit shared by all requests going through this client
+    fn wait_for_turn(&self) {
+        let wait = {
+            let mut full = self.bucket_full.lock().unwrap();
+            let now = Instant::now();
+            take_token(&mut full, now, &self.retry) - now
+        };
+        thread::sleep(wait);
+    }
+
+    fn send(
+        &self,
+        url: &str,
+        query: &[(&str, &str)],
+        accept: Option<&str>,
+        headers: HeaderMap,
+    ) -> Result<Response, FetchError> {
+        let mut attempt = 0;
+        loop {
+            self.wait_for_turn();
+
+            let mut req = self
+                .client
+                .get(url)
+                .header(USER_AGENT, "gwrust")
+                .headers(headers.clone())
+                .query(query);
+            if let Some(accept) = accept {
+                req = req.header(ACCEPT, accept);
+            }
+            let result = req.send();
+
+            // Only temporary failures are worth another try
+            let retry = match &result {
+                Ok(res) if res.status().is_server_error() => Some(retry_after(res)),
+                Ok(res) if res.status() == StatusCode::TOO_MANY_REQUESTS => Some(retry_after(res)),
+                Ok(_) => None,
+                Err(e) if e.is_timeout() || e.is_connect() => Some(None),
+                Err(_) => None,
+            };
+
+            match retry {
+                Some(server_delay) if attempt < self.retry.max_retries => {
+                    // However long the server asks us to wait, we do not
+                    // keep the installation silent for hours
+                    let delay = server_delay
+                        .map(|delay| delay.min(self.retry.max_delay))
+                        .unwrap_or_else(|| self.retry.backoff(attempt));
+                    println!(
+                        "Request to {} failed ({}). Retrying in {:.1} s.",
+                        url,
+                        match &result {
+                            Ok(res) => res.status().to_string(),
+                            Err(e) => e.to_string(),
+                        },
+                        delay.as_secs_f32()
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                _ => return Ok(result?),
+            }
+        }
+    }
+
+    fn get(
+        &self,
+        url: &str,
+        query: &[(&str, &str)],
+        accept: Option<&str>,
+    ) -> Result<Vec<u8>, FetchError> {
+        let parsed =
+            if query.is_empty() { Url::parse(url) } else { Url::parse_with_params(url, query) };
+        let full_url = parsed.map_err(|_| FetchError::InvalidUrl(url.to_string()))?;
+
+        if let HttpMode::Replay(dir) = &self.http_mode {
+            let path = fixtures::fixture_path(dir, &full_url);
+            if let Ok(status) = fs::read_to_string(status_path(&path)) {
+                let status = status.trim().parse().unwrap_or(0);
+                return Err(FetchError::Status { status, url: full_url.to_string() });
+            }
+            println!("Replaying {} from {:?}.", full_url, path);
+            return fs::read(&path).map_err(|error| FetchError::Fixture { path, error });
+        }
+
+        // Only the JSON documents are polled again and again. Skymaps are
+        // kept in the cache folder anyway.
+        let cached = match &self.http_cache {
+            Some(dir) if accept == Some(JSON) => Some(fixtures::fixture_path(dir, &full_url)),
+            _ => None,
+        };
+        let validators = cached.as_deref().and_then(Validators::read);
+
+        let mut headers = HeaderMap::new();
+        if let Some(validators) = &validators {
+            if let Some(etag) = validators.etag.as_deref().and_then(|v| v.parse().ok()) {
+                headers.insert(IF_NONE_MATCH, etag);
+            }
+            if let Some(date) = validators.last_modified.as_deref().and_then(|v| v.parse().ok()) {
+                headers.insert(IF_MODIFIED_SINCE, date);
+            }
+        }
+
+        let res = self.send(url, query, accept, headers)?;
+        let status = res.status();
+
+        let record_path = match &self.http_mode {
+            // Under the URL asked for, not the one redirected to, so that
+            // the replay finds it
+            HttpMode::Record(dir) => {
+                let path = fixtures::fixture_path(dir, &full_url);
+                fs::create_dir_all(path.parent().unwrap_or(dir))
+                    .map_err(|error| FetchError::Fixture { path: path.clone(), error })?;
+                Some(path)
+            }
+            _ => None,
+        };
+
+        let body = match (status, &cached) {
+            (StatusCode::NOT_MODIFIED, Some(path)) if validators.is_some() => {
+                println!("{} not modified.", full_url);
+                fs::read(path)?
+            }
+            (status, _) if status.is_success() => {
+                let new_validators = Validators::from_response(&res);
+                let expected = res.content_length();
+                let body = res.bytes()?.to_vec();
+                if let Some(expected) = expected.filter(|n| *n != body.len() as u64) {
+                    let received = body.len() as u64;
+                    return Err(FetchError::Incomplete {
+                        url: full_url.to_string(),
+                        expected,
+                        received,
+                    });
+                }
+                if let (Some(path), Some(new_validators)) = (&cached, new_validators) {
+                    fs::create_dir_all(path.parent().unwrap_or(path))?;
+                    cache::write_atomically(path, &body)?;
+                    new_validators.write(path)?;
+                }
+                body
+            }
+            (status, _) => {
+                if let Some(path) = &record_path {
+                    let path = status_path(path);
+                    fs::write(&path, status.as_str())
+                        .map_err(|error| FetchError::Fixture { path, error })?;
+                }
+                return Err(FetchError::Status {
+                    status: status.as_u16(),
+                    url: full_url.to_string(),
+                });
+            }
+        };
 
-    let json = res.text()?;
-    println!("Parsing json: {}…", &json[0..60]);
+        if let Some(path) = &record_path {
+            println!("Recording {} to {:?}.", url, path);
+            fs::write(path, &body)
+                .map_err(|error| FetchError::Fixture { path: path.clone(), error })?;
+        }
+
+        Ok(body)
+    }
+}
+
+/// ETag and Last-Modified of a response we have kept, stored next to its body.
+#[derive(Debug, Serialize, Deserialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn path(body: &Path) -> PathBuf {
+        let mut name = body.file_name().unwrap_or_default().to_os_string();
+        name.push(".validators");
+        body.with_file_name(name)
+    }
+
+    fn read(body: &Path) -> Option<Validators> {
+        if !body.exists() {
+            return None;
+        }
+        let text = fs::read_to_string(Self::path(body)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn write(&self, body: &Path) -> Result<(), FetchError> {
+        cache::write_atomically(&Self::path(body), &serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    fn from_response(res: &Response) -> Option<Validators> {
+        let header = |name| res.headers().get(name).and_then(|v| v.to_str().ok()).map(String::from);
+        let validators = Validators { etag: header(ETAG), last_modified: header(LAST_MODIFIED) };
+        (validators.etag.is_some() || validators.last_modified.is_some()).then_some(validators)
+    }
+}
+
+// Failed responses are recorded as their status code next to where the body
+// would have gone
+fn status_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".status");
+    path.with_file_name(name)
+}
+
+fn read_json<T: DeserializeOwned + std::fmt::Debug>(
+    url: &str,
+    gracedb: &GraceDbClient,
+) -> Result<T, FetchError> {
+    let body = gracedb.get(url, &[], Some(JSON))?;
+
+    let json = String::from_utf8_lossy(&body);
+    println!("Parsing json: {}…", preview(&json));
 
     let conv = serde_json::from_str(&json)?;
     println!("{:?}", conv);
     Ok(conv)
 }
 
-fn download_fits(
-    file_path: &String,
-    url: &String,
-    client: &reqwest::blocking::Client,
-) -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let cache_folder = Path::new(CACHE_FOLDER);
+// Every FITS file consists of blocks of 2880 bytes and starts with a header
+fn verify_fits(path: &Path) -> Result<(), FetchError> {
+    let len = fs::metadata(path)?.len();
+    if len == 0 || len % 2880 != 0 {
+        let reason = format!("size {} is not a multiple of 2880 bytes", len);
+        return Err(FetchError::Fits { path: path.to_path_buf(), reason });
+    }
+    // Headers and checksums only, the skymap itself is read when played
+    let bytes = fs::read(path)?;
+    fits::hdus(&bytes)
+        .and_then(|hdus| hdus.iter().try_for_each(fits::Hdu::verify_checksums))
+        .map_err(|reason| FetchError::Fits { path: path.to_path_buf(), reason })
+}
+
+// Name of a skymap in the cache folder after the superevent id
+pub const SKYMAP_SUFFIX: &str = "-bayestar.multiorder.fits";
 
-    let _ = fs::create_dir(cache_folder); // ignore if cache already exists or otherwise fails
+/// Where the skymap of a superevent is kept in the cache folder.
+pub fn skymap_path(cache_dir: &Path, superevent_id: &str) -> PathBuf {
+    cache_dir.join(format!("{}{}", superevent_id, SKYMAP_SUFFIX))
+}
 
-    let file_path = cache_folder.join(file_path);
-    let metadata = std::fs::metadata(&file_path);
+fn download_fits(
+    file_path: PathBuf,
+    url: &str,
+    gracedb: &GraceDbClient,
+) -> Result<PathBuf, FetchError> {
+    let _ = fs::create_dir_all(&gracedb.cache_dir); // ignore if cache already exists or otherwise fails
 
-    if metadata.is_ok() {
-        println!("File {:?} exists. Not downloading.", &file_path);
-        return Ok(file_path);
+    if file_path.exists() {
+        match verify_fits(&file_path) {
+            Ok(()) => {
+                println!("File {:?} exists. Not downloading.", &file_path);
+                // Mark as recently used for the cache eviction
+                let _ = fs::File::options()
+                    .append(true)
+                    .open(&file_path)
+                    .and_then(|f| f.set_modified(SystemTime::now()));
+                return Ok(file_path);
+            }
+            Err(e) => {
+                println!("Removing broken file {:?}: {}", &file_path, e);
+                fs::remove_file(&file_path)?;
+            }
+        }
     }
 
-    let res = client.get(url).header(USER_AGENT, "gwrust").send()?.error_for_status()?;
+    let body = gracedb.get(url, &[], None)?;
 
-    let n_bytes = res.content_length().unwrap_or(0);
+    let n_bytes = body.len();
 
-    let mut file = std::fs::File::create(&file_path)?;
-    let mut content = std::io::Cursor::new(res.bytes()?);
+    // Only complete and readable files may appear under the final name
+    let part_path = cache::part_path(&file_path);
 
-    println!("Writing {n_bytes} to {:?}.", &file_path);
-    std::io::copy(&mut content, &mut file)?;
+    println!("Writing {n_bytes} to {:?}.", &part_path);
+    let written = fs::File::create(&part_path).and_then(|mut file| {
+        file.write_all(&body)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.map_err(FetchError::from).and_then(|_| verify_fits(&part_path)) {
+        let _ = fs::remove_file(&part_path);
+        return Err(e);
+    }
+
+    fs::rename(&part_path, &file_path)?;
     Ok(file_path)
 }
 
+// Files being written are left alone for this long
+const STALE_PART: Duration = Duration::from_secs(3600);
+
+/// Keeps what gwrust downloads into the cache folder within `max_bytes` and
+/// removes what was not used for `max_age`: skymaps, left over `.part` files
+/// and the responses kept under `http/`. The least recently used go first.
+/// Events.json and the skymaps of its events and of `keep` stay. Skipped while
+/// other instances are using the folder.
+pub fn evict_cache(
+    cache_dir: &Path,
+    keep: &[GWEvent],
+    max_bytes: u64,
+    max_age: Duration,
+) -> Result<(), FetchError> {
+    // Everything below http/ is ours, elsewhere only the skymaps and parts
+    fn collect(
+        dir: &Path,
+        http: bool,
+        files: &mut Vec<(PathBuf, SystemTime, u64)>,
+    ) -> std::io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if metadata.is_dir() {
+                if http || name == "http" {
+                    collect(&path, true, files)?;
+                }
+            } else if !cache::is_lock_file(&path)
+                && (http || name.ends_with(SKYMAP_SUFFIX) || name.ends_with(".part"))
+            {
+                files.push((path, metadata.modified()?, metadata.len()));
+            }
+        }
+        Ok(())
+    }
+
+    if !cache_dir.exists() {
+        return Ok(());
+    }
+    let Some(_lock) = cache::try_lock_cache_dir_exclusive(cache_dir)? else {
+        println!("Cache {:?} is in use by another gwrust. Not evicting.", cache_dir);
+        return Ok(());
+    };
+
+    let events_cache = cache_dir.join(cache::EVENTS_CACHE);
+    let cached = cache::read_cache(&events_cache).map(|cached| cached.events).unwrap_or_default();
+    let kept: Vec<PathBuf> =
+        keep.iter().chain(&cached).map(|event| skymap_path(cache_dir, &event.id)).collect();
+
+    let mut files = Vec::new();
+    collect(cache_dir, false, &mut files)?;
+    files.sort_by_key(|(_, modified, _)| *modified);
+
+    let now = SystemTime::now();
+    let age = |modified: SystemTime| now.duration_since(modified).unwrap_or_default();
+    let mut total: u64 = files.iter().map(|(_, _, len)| len).sum::<u64>()
+        + fs::metadata(&events_cache).map_or(0, |metadata| metadata.len());
+
+    for (path, modified, len) in files {
+        let writing =
+            path.extension().is_some_and(|ext| ext == "part") && age(modified) < STALE_PART;
+        if kept.contains(&path) || writing {
+            continue;
+        }
+        if age(modified) <= max_age && total <= max_bytes {
+            break;
+        }
+        println!("Evicting {:?} from cache.", path);
+        match fs::remove_file(&path) {
+            Ok(()) => total -= len,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => total -= len,
+            // Go on with the rest
+            Err(e) => println!("Could not evict {:?}: {}", path, e),
+        }
+    }
+
+    Ok(())
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
-struct FitsParams {
-    dist_mean: f64,
-    dist_std: f64,
-    instruments: Vec<String>,
+pub struct FitsParams {
+    pub dist_mean: f64,
+    pub dist_std: f64,
+    pub instruments: Vec<String>,
+    // From the pixels, if the file has them
+    pub area_90_deg2: Option<f64>,
+    pub max_probability: Option<SkyPosition>,
 }
 
-const CACHE_FOLDER: &str = "cache";
+const JSON: &str = "application/json";
 
-fn read_fits(filename: &Path) -> Result<FitsParams, Box<dyn std::error::Error>> {
-    let fits = Fits::open(filename).expect("Failed to open");
+/// Confirmed superevents only
+pub const SUPEREVENT_QUERY: &str = "category: Production label: SIGNIF_LOCKED";
+
+pub fn read_fits(filename: &Path) -> Result<FitsParams, FetchError> {
+    let bytes = fs::read(filename)?;
+    let hdus = fits::hdus(&bytes)
+        .map_err(|reason| FetchError::Fits { path: filename.to_path_buf(), reason })?;
 
     let mut dist_mean = 0.0;
     let mut dist_std = 0.0;
     let mut instruments: Vec<String> = Vec::new();
 
-    // Iterate over HDUs
-    for hdu in fits.iter() {
-        for (header, value) in hdu.iter() {
-            if header == "DISTMEAN" {
-                if let Some(RealFloatingNumber(v)) = value {
-                    dist_mean = *v;
-                }
-            }
-            if header == "DISTSTD" {
-                if let Some(RealFloatingNumber(v)) = value {
-                    dist_std = *v;
-                }
-            }
-            if header == "INSTRUME" {
-                if let Some(CharacterString(v)) = value {
-                    instruments = v.split(",").map(|x| x.to_string()).collect();
-                }
-            }
-            println!("{:?} {:?}", header, value);
+    // The keys may be in any of the HDUs, the last one wins
+    for hdu in &hdus {
+        if let Some(v) = hdu.float("DISTMEAN") {
+            dist_mean = v;
         }
+        if let Some(v) = hdu.float("DISTSTD") {
+            dist_std = v;
+        }
+        if let Some(v) = hdu.value("INSTRUME") {
+            instruments = v.split(",").map(|x| x.to_string()).collect();
+        }
+    }
+
+    let (area_90_deg2, max_probability) = match Skymap::read(filename) {
+        Ok(skymap) => (Some(skymap.credible_area_deg2(0.9)), Some(skymap.max_probability())),
+        Err(e) => {
+            println!("No skymap in {:?}: {}", filename, e);
+            (None, None)
+        }
+    };
+
+    Ok(FitsParams { dist_mean, dist_std, instruments, area_90_deg2, max_probability })
+}
+
+// Everything that went wrong for one event, even if it could be read
+type EventResult = (Result<Option<GWEvent>, FetchError>, Vec<(String, FetchError)>);
+
+fn read_event(
+    event: &GraceDbListEvent,
+    gracedb: &GraceDbClient,
+    failures: &mut Vec<(String, FetchError)>,
+) -> Result<Option<GWEvent>, FetchError> {
+    let Some(files) = event.links.get("files") else {
+        return Ok(None);
+    };
+
+    let body = gracedb.get(files, &[], Some(JSON))?;
+    let text = String::from_utf8_lossy(&body);
+    let files_map: HashMap<String, String> = serde_json::from_str(&text)?;
+
+    // We are interested in the files update.json, which containes all
+    // the obvious metadata for a _confirmed_ event.
+    // For sky analysis, we need to look at the file bayestar.mulitorder.fits,
+    // which contains the distance and instruments etc.
+
+    let update_json = format!("{}-update.json", event.superevent_id);
+
+    let Some(url) = files_map.get(&update_json) else {
+        println!("Warning: No file {} found for event {}", update_json, event.superevent_id);
+        return Ok(None);
+    };
+
+    let mut eventdata: GraceDbEvent = read_json(url, gracedb)?;
+
+    // em-bright and p_astro are uploaded on their own and may be newer than
+    // the copy in update.json
+    if let Some(url) = files_map.get("em_bright.json") {
+        match read_json(url, gracedb) {
+            Ok(properties) => eventdata.event.properties = properties,
+            Err(e) => failures.push((event.superevent_id.clone(), e)),
+        }
+    }
+    let pipeline_p_astro = format!("{}.p_astro.json", eventdata.event.pipeline);
+    if let Some(url) = files_map.get("p_astro.json").or(files_map.get(&pipeline_p_astro)) {
+        match read_json(url, gracedb) {
+            Ok(classification) => eventdata.event.classification = classification,
+            Err(e) => failures.push((event.superevent_id.clone(), e)),
+        }
+    }
+
+    let mut fits_data = None;
+    if let Some(url) = files_map.get("bayestar.multiorder.fits") {
+        let file_path = skymap_path(&gracedb.cache_dir, &event.superevent_id);
+        // Without the skymap we can still play the event
+        match download_fits(file_path, url, gracedb).and_then(|path| read_fits(&path)) {
+            Ok(data) => fits_data = Some(data),
+            Err(e) => failures.push((event.superevent_id.clone(), e)),
+        }
+        println!("Fits data: {:?}", fits_data);
+    } else {
+        println!("No fits file bayestar.multiorder.fits found. Skipping.")
     }
 
-    Ok(FitsParams { dist_mean, dist_std, instruments })
+    Ok(Some(gracedb_to_gwevent(eventdata, fits_data)))
+}
+
+/// Reads the events with up to `workers` threads in parallel. The results
+/// are in the order of `events`.
+fn read_events(
+    events: &[GraceDbListEvent],
+    gracedb: &GraceDbClient,
+    workers: usize,
+) -> Vec<EventResult> {
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<EventResult>>> =
+        events.iter().map(|_| Mutex::new(None)).collect();
+
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, events.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(event) = events.get(i) else {
+                    break;
+                };
+                println!("{event:?}");
+
+                let mut failures = Vec::new();
+                let result = read_event(event, gracedb, &mut failures);
+                *results[i].lock().unwrap() = Some((result, failures));
+            });
+        }
+    });
+
+    results.into_iter().filter_map(|slot| slot.into_inner().unwrap()).collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    pub last_n: usize,
+    // Number of events fetched in parallel
+    pub workers: usize,
+    // Limit for following the `next` links of the superevents list
+    pub max_pages: usize,
 }
 
 // blocking IO
-pub fn read_gracedb(last_n: usize) -> Result<Vec<GWEvent>, Box<dyn std::error::Error>> {
-    let client = Client::new();
-    let mut result: Vec<GWEvent> = Vec::new();
-
-    let url = "https://gracedb.ligo.org/apiweb/superevents/";
-    let res = client
-        .get(url)
-        .header(USER_AGENT, "gwrust")
-        .header(ACCEPT, "application/json")
-        .query(&[("query", "category: Production label: SIGNIF_LOCKED")])
-        .send()?
-        .error_for_status()?;
-    //let content = res;
-    let text = res.text()?;
-
-    println!("Parsing json: {}…", &text[0..60]);
-
-    let gw: GraceDbList = serde_json::from_str(&text)?;
-    for event in gw.superevents.iter().take(last_n) {
-        println!("{event:?}");
-
-        if let Some(files) = event.links.get("files") {
-            let res = client
-                .get(files)
-                .header(USER_AGENT, "gwrust")
-                .header(ACCEPT, "application/json")
-                .send()?
-                .error_for_status()?;
+pub fn read_gracedb(
+    gracedb: &GraceDbClient,
+    options: &FetchOptions,
+) -> Result<FetchReport, FetchError> {
+    let mut report = FetchReport { events: Vec::new(), failures: Vec::new() };
 
-            let text = res.text()?;
-            let files_map: HashMap<String, String> = serde_json::from_str(&text)?;
+    let mut superevents: Vec<GraceDbListEvent> = Vec::new();
+    let first_page = gracedb.superevents_url();
+    let mut page = Some(first_page.clone());
+    let mut n_pages = 0;
 
-            // We are interested in the files update.json, which containes all
-            // the obvious metadata for a _confirmed_ event.
-            // For sky analysis, we need to look at the file bayestar.mulitorder.fits,
-            // which contains the distance and instruments etc.
+    while let Some(url) = page.take() {
+        let query: &[(&str, &str)] = if url == first_page {
+            &[("query", SUPEREVENT_QUERY)]
+        } else {
+            // The next links already carry the query
+            &[]
+        };
+        let body = gracedb.get(&url, query, Some(JSON))?;
+        let text = String::from_utf8_lossy(&body);
 
-            let update_json = format!("{}-update.json", event.superevent_id);
+        println!("Parsing json: {}…", preview(&text));
 
-            if let Some(url) = files_map.get(&update_json) {
-                let eventdata = read_gracedbevent(url, &client)?;
+        let gw: GraceDbList = serde_json::from_str(&text)?;
+        superevents.extend(gw.superevents);
+        n_pages += 1;
+        println!("Got {} of {} superevents.", superevents.len(), gw.num_rows);
 
-                let mut fits_data = None;
-                if let Some(url) = files_map.get("bayestar.multiorder.fits") {
-                    let gen_name =
-                        format!("{}-{}", event.superevent_id, "bayestar.multiorder.fits");
-                    let file_path = download_fits(&gen_name, url, &client)?;
+        if superevents.len() < options.last_n && n_pages < options.max_pages {
+            page = gw.links.get("next").cloned().flatten();
+        }
+    }
 
-                    fits_data = read_fits(&file_path).ok();
-                    println!("Fits data: {:?}", fits_data);
-                } else {
-                    println!("No fits file bayestar.multiorder.fits found. Skipping.")
-                }
+    let events = &superevents[..options.last_n.min(superevents.len())];
+
+    for (event, (result, failures)) in
+        events.iter().zip(read_events(events, gracedb, options.workers))
+    {
+        // One broken event must not cost us the others
+        match result {
+            Ok(Some(gwevent)) => report.events.push(gwevent),
+            Ok(None) => {}
+            Err(e) => report.failures.push((event.superevent_id.clone(), e)),
+        }
+        report.failures.extend(failures);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_percentiles() {
+        let event = GWEvent {
+            distance_mpc: 400.0,
+            distance_std_mpc: 100.0,
+            ..GWEvent::blank("S1", Utc::now())
+        };
+        assert!((event.distance_percentile_mpc(0.5) - 400.0).abs() < 1e-9);
+        assert!((event.distance_percentile_mpc(0.95) - (400.0 + 100.0 * NORMAL_90)).abs() < 1e-6);
+        assert!((event.distance_percentile_mpc(0.05) - (400.0 - 100.0 * NORMAL_90)).abs() < 1e-6);
+        // Never closer than here
+        assert_eq!(event.distance_percentile_mpc(1e-6), 0.0);
 
-                let gwevent = gracedb_to_gwevent(eventdata, fits_data);
-                result.push(gwevent);
-            } else {
-                println!(
-                    "Warning: No file {} found for event {}",
-                    update_json, event.superevent_id
-                );
+        // A published interval is followed on either side
+        let skewed =
+            GWEvent { distance_lower_mpc: Some(300.0), distance_upper_mpc: Some(700.0), ..event };
+        assert!((skewed.distance_percentile_mpc(0.05) - 300.0).abs() < 1e-6);
+        assert!((skewed.distance_percentile_mpc(0.95) - 700.0).abs() < 1e-6);
+        assert!((skewed.distance_percentile_mpc(0.5) - 400.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn retry_after_seconds_and_dates() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z").unwrap().to_utc();
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now),
+            Some(Duration::from_secs(120))
+        );
+        // A date already past asks for no particular delay
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:20:00 GMT", now), None);
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn bursts_then_spaces_requests() {
+        let retry =
+            RetryPolicy { min_interval: Duration::from_secs(1), burst: 3, ..Default::default() };
+        let now = Instant::now();
+        let mut full = now;
+        let starts: Vec<Duration> =
+            (0..5).map(|_| take_token(&mut full, now, &retry) - now).collect();
+        let secs = Duration::from_secs;
+        assert_eq!(starts, [secs(0), secs(0), secs(0), secs(1), secs(2)]);
+
+        // After a quiet while the whole burst is available again
+        let later = now + secs(60);
+        assert_eq!(take_token(&mut full, later, &retry), later);
+        assert_eq!(take_token(&mut full, later, &retry), later);
+
+        // A single token spaces every request
+        let retry = RetryPolicy { burst: 1, ..retry };
+        let mut full = now;
+        assert_eq!(take_token(&mut full, now, &retry), now);
+        assert_eq!(take_token(&mut full, now, &retry), now + secs(1));
+    }
+
+    #[test]
+    fn evicts_only_our_files() {
+        let dir = std::env::temp_dir().join(format!("gwrust-evict-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("http/superevents")).unwrap();
+        fs::create_dir_all(dir.join("backup")).unwrap();
+        let source = cache::CacheSource { url: String::new(), query: String::new() };
+        let cached = cache::CacheEnvelope::new(&source, vec![GWEvent::blank("S1", Utc::now())]);
+        cache::write_to_cache(&cached, &dir.join(cache::EVENTS_CACHE)).unwrap();
+
+        let now = SystemTime::now();
+        let days = |n: u64| now - Duration::from_secs(n * 24 * 3600);
+        let files = [
+            (cache::EVENTS_CACHE, 0, days(60)),
+            ("S1-bayestar.multiorder.fits", 10, days(60)),
+            ("S2-bayestar.multiorder.fits", 10, days(60)),
+            ("S3-bayestar.multiorder.fits", 10_000, days(3)),
+            ("S4-bayestar.multiorder.fits", 10_000, days(2)),
+            ("S5-bayestar.multiorder.fits", 10_000, days(1)),
+            ("S6-bayestar.multiorder.fits.1.part", 10, days(60)),
+            ("S7-bayestar.multiorder.fits.1.part", 10, now),
+            ("http/superevents/S2.json", 10, days(60)),
+            ("http/superevents/S2.json.validators", 10, days(60)),
+            ("notes.txt", 10, days(60)),
+            ("backup/S0-bayestar.multiorder.fits", 10, days(60)),
+        ];
+        for (name, len, modified) in files {
+            let path = dir.join(name);
+            if len > 0 {
+                fs::write(&path, vec![0; len]).unwrap();
             }
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(modified).unwrap();
         }
+
+        // Only S4, which is playing, fits into the limit
+        let keep = [GWEvent::blank("S4", Utc::now())];
+        evict_cache(&dir, &keep, 15_000, Duration::from_secs(30 * 24 * 3600)).unwrap();
+
+        let left: Vec<&str> =
+            files.iter().map(|(name, _, _)| *name).filter(|name| dir.join(name).exists()).collect();
+        assert_eq!(
+            left,
+            [
+                cache::EVENTS_CACHE,
+                "S1-bayestar.multiorder.fits",
+                "S4-bayestar.multiorder.fits",
+                "S7-bayestar.multiorder.fits.1.part",
+                "notes.txt",
+                "backup/S0-bayestar.multiorder.fits",
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
     }
 
-    Ok(result)
+    #[test]
+    fn backoff_is_capped() {
+        let retry = RetryPolicy {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            ..Default::default()
+        };
+        for attempt in 0..4 {
+            let full = Duration::from_secs(1 << attempt);
+            let delay = retry.backoff(attempt);
+            assert!(delay >= full / 2 && delay <= full, "{:?} at {}", delay, attempt);
+        }
+        for attempt in [5, 10, 40, u32::MAX] {
+            let delay = retry.backoff(attempt);
+            assert!(delay >= retry.max_delay / 2 && delay <= retry.max_delay);
+        }
+    }
 }
@@ -0,0 +1,119 @@
+// The interferometers of the network and when a wavefront from a given sky
+// position reaches each of them. Site coordinates from LALSuite
+// (LALDetectors.h), on the WGS84 ellipsoid.
+
+use crate::datafetch::GWEvent;
+use crate::observer;
+use crate::physics::SPEED_OF_LIGHT;
+
+// WGS84 semi-major axis in meters and flattening
+const EARTH_RADIUS_M: f64 = 6_378_137.0;
+const EARTH_FLATTENING: f64 = 1.0 / 298.257_223_563;
+
+#[derive(Debug, PartialEq)]
+pub struct Detector {
+    pub name: &'static str,
+    /// Geodetic latitude and longitude of the vertex in degrees, east positive
+    pub lat: f64,
+    pub lon: f64,
+    /// Height above the ellipsoid in meters
+    pub height: f64,
+}
+
+pub const DETECTORS: [Detector; 4] = [
+    Detector { name: "H1", lat: 46.455_144, lon: -119.407_656, height: 142.554 },
+    Detector { name: "L1", lat: 30.562_894, lon: -90.774_242, height: -6.574 },
+    Detector { name: "V1", lat: 43.631_414, lon: 10.504_497, height: 51.884 },
+    Detector { name: "K1", lat: 36.411_940, lon: 137.305_910, height: 414.181 },
+];
+
+pub fn find(name: &str) -> Option<&'static Detector> {
+    DETECTORS.iter().find(|d| d.name == name)
+}
+
+impl Detector {
+    /// Earth fixed cartesian coordinates in meters.
+    pub fn position(&self) -> [f64; 3] {
+        let (lat, lon) = (self.lat.to_radians(), self.lon.to_radians());
+        let e2 = EARTH_FLATTENING * (2.0 - EARTH_FLATTENING);
+        let n = EARTH_RADIUS_M / (1.0 - e2 * lat.sin() * lat.sin()).sqrt();
+        [
+            (n + self.height) * lat.cos() * lon.cos(),
+            (n + self.height) * lat.cos() * lon.sin(),
+            (n * (1.0 - e2) + self.height) * lat.sin(),
+        ]
+    }
+}
+
+/// Seconds after the first detector at which the wavefront from the most
+/// probable position of `event` passes each of its detectors, earliest first.
+/// `None` without a sky position.
+pub fn arrival_offsets(event: &GWEvent) -> Option<Vec<(&'static Detector, f64)>> {
+    let position = event.sky_position?;
+
+    // Unit vector towards the source in the Earth fixed frame
+    let lon = (position.ra - observer::gmst(event.time)).to_radians();
+    let dec = position.dec.to_radians();
+    let towards = [dec.cos() * lon.cos(), dec.cos() * lon.sin(), dec.sin()];
+
+    // Detectors further towards the source see the wave earlier
+    let mut arrivals: Vec<(&Detector, f64)> = event
+        .detectors
+        .iter()
+        .filter_map(|name| find(name))
+        .map(|detector| {
+            let r = detector.position();
+            let ahead_m: f64 = r.iter().zip(towards).map(|(r, n)| r * n).sum();
+            (detector, -ahead_m / (SPEED_OF_LIGHT * 1e3))
+        })
+        .collect();
+    arrivals.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    let first = arrivals.first()?.1;
+    Some(arrivals.into_iter().map(|(detector, t)| (detector, t - first)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::skymap::SkyPosition;
+    use chrono::{TimeZone, Utc};
+
+    fn event(detectors: &[&str], ra: f64, dec: f64) -> GWEvent {
+        let time = Utc.with_ymd_and_hms(2019, 4, 25, 8, 18, 5).unwrap();
+        GWEvent {
+            detectors: detectors.iter().map(|name| name.to_string()).collect(),
+            sky_position: Some(SkyPosition { ra, dec }),
+            ..GWEvent::blank("S1", time)
+        }
+    }
+
+    #[test]
+    fn hanford_and_livingston_within_light_travel_time() {
+        for ra in (0..360).step_by(15) {
+            for dec in (-90..=90).step_by(15) {
+                let offsets =
+                    arrival_offsets(&event(&["H1", "L1"], ra as f64, dec as f64)).unwrap();
+                assert_eq!(offsets.len(), 2);
+                assert_eq!(offsets[0].1, 0.0);
+                assert!((0.0..=0.0101).contains(&offsets[1].1), "{:?}", offsets);
+            }
+        }
+        assert_eq!(
+            arrival_offsets(&GWEvent { sky_position: None, ..event(&["H1"], 0.0, 0.0) }),
+            None
+        );
+    }
+
+    #[test]
+    fn reaches_the_detector_below_the_source_first() {
+        for overhead in ["H1", "L1", "V1"] {
+            let detector = find(overhead).unwrap();
+            let time = event(&[], 0.0, 0.0).time;
+            let ra = (detector.lon + observer::gmst(time)).rem_euclid(360.0);
+            let offsets = arrival_offsets(&event(&["H1", "L1", "V1"], ra, detector.lat)).unwrap();
+            assert_eq!(offsets[0].0.name, overhead);
+            assert!(offsets[1].1 > 0.0);
+        }
+    }
+}
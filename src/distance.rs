@@ -0,0 +1,107 @@
+// Distance bands from the planning notes ("<2000Pc<4000Pc<", "Lautstärke =
+// Entfernung"): a nearby event gets long, loud bursts of the 200/201 Hz
+// triangles, a distant one short, faint blips. The notes count in Pc, the
+// alerts in Mpc.
+
+/// Lengths the Triangle 200–203 samples ship in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Long,
+    Medium,
+    Short,
+}
+
+impl Variant {
+    /// Nearest band first
+    pub const ALL: [Variant; 3] = [Variant::Long, Variant::Medium, Variant::Short];
+
+    pub fn secs(self) -> u64 {
+        match self {
+            Variant::Long => 10,
+            Variant::Medium => 5,
+            Variant::Short => 2,
+        }
+    }
+}
+
+// Factor to the volume of the cues, nearest band first
+const LEVELS: [f32; 3] = [1.0, 0.6, 0.3];
+
+// The bursts of the composition before there were bands: twelve 2 s cues
+const CLASSIC_REPEATS: u32 = 12;
+
+/// How the bursts sound for one event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    pub variant: Variant,
+    /// Number of cues in each burst
+    pub repeats: u32,
+    /// Factor to the volume of the cues
+    pub level: f32,
+}
+
+/// Band of an event `distance_mpc` away, given the two limits between the
+/// near, middle and far bands and the repeats of each. Events of unknown
+/// distance (0) get the classic bursts.
+pub fn band(distance_mpc: f64, limits_mpc: &[f64; 2], repeats: &[u32; 3]) -> Band {
+    if distance_mpc <= 0.0 {
+        return Band { variant: Variant::Short, repeats: CLASSIC_REPEATS, level: 1.0 };
+    }
+    let index = limits_mpc.iter().filter(|limit| distance_mpc >= **limit).count();
+    Band { variant: Variant::ALL[index], repeats: repeats[index], level: LEVELS[index] }
+}
+
+fn parse_list<T: std::str::FromStr, const N: usize>(value: &str) -> Result<[T; N], String>
+where
+    T::Err: std::fmt::Display,
+{
+    let items = value
+        .split(',')
+        .map(|item| item.trim().parse::<T>().map_err(|e| format!("{:?}: {}", item, e)))
+        .collect::<Result<Vec<T>, String>>()?;
+    let count = items.len();
+    items.try_into().map_err(|_| format!("needs {} comma separated values, not {}", N, count))
+}
+
+/// Parses the limits between the bands, e.g. "2000,4000".
+pub fn parse_limits(value: &str) -> Result<[f64; 2], String> {
+    let limits: [f64; 2] = parse_list(value)?;
+    if !limits.iter().all(|limit| limit.is_finite() && *limit > 0.0) || limits[0] > limits[1] {
+        return Err("needs two ascending positive distances".to_string());
+    }
+    Ok(limits)
+}
+
+/// Parses the repeats of the near, middle and far bands, e.g. "4,6,12".
+pub fn parse_repeats(value: &str) -> Result<[u32; 3], String> {
+    parse_list(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bands() {
+        let (limits, repeats) = ([2000.0, 4000.0], [4, 6, 12]);
+        assert_eq!(band(100.0, &limits, &repeats).variant, Variant::Long);
+        assert_eq!(band(2000.0, &limits, &repeats).variant, Variant::Medium);
+        assert_eq!(band(5000.0, &limits, &repeats).repeats, 12);
+        assert_eq!(
+            band(0.0, &limits, &repeats),
+            Band { variant: Variant::Short, repeats: CLASSIC_REPEATS, level: 1.0 }
+        );
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(parse_limits("2000, 4000"), Ok([2000.0, 4000.0]));
+        assert!(parse_limits("4000,2000").is_err());
+        assert!(parse_limits("2000").is_err());
+        assert!(parse_limits("-1,2000").is_err());
+        assert!(parse_limits("NaN,2000").is_err());
+        assert_eq!(parse_repeats("4,6,12"), Ok([4, 6, 12]));
+        assert!(parse_repeats("4,6").is_err());
+        assert!(parse_repeats("4,6,x").is_err());
+    }
+}
@@ -0,0 +1,406 @@
+// Images for the projection next to the sound: one SVG per event with its
+// skymap, detectors, distance and classification, and one of the timeline
+// of the voices. Files are replaced atomically, so an image viewer watching
+// the folder never shows a half written one.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Local, Utc};
+
+use crate::cache;
+use crate::datafetch::{self, GWEvent};
+use crate::detectors::DETECTORS;
+use crate::observer;
+use crate::skymap::{SkyPosition, Skymap};
+use crate::skyview::{self, CREDIBLE_LEVELS};
+
+pub const NOW_PLAYING: &str = "now_playing";
+pub const TIMELINE: &str = "timeline";
+
+const WIDTH: f64 = 960.0;
+const HEIGHT: f64 = 720.0;
+const BACKGROUND: &str = "#000010";
+const FOREGROUND: &str = "#d0d0e0";
+const DIM: &str = "#505068";
+const DETECTOR_COLOR: &str = "#40c0ff";
+
+// The map, centered in the upper part
+const MAP_X: f64 = 80.0;
+const MAP_Y: f64 = 80.0;
+const MAP_WIDTH: f64 = 800.0;
+const MAP_HEIGHT: f64 = 400.0;
+// Raster cells of the credible regions
+const CELLS_X: usize = 200;
+const CELLS_Y: usize = 100;
+
+// Density relative to the peak from which a cell gets the color
+const DENSITY_COLORS: [(f64, &str); 4] =
+    [(0.5, "#ffff80"), (0.2, "#ff9030"), (0.05, "#d02040"), (0.01, "#701060")];
+const FAINT_COLOR: &str = "#301040";
+
+// Timeline of the voices, from this long ago to as long ahead
+const TIMELINE_WINDOW_MINUTES: i64 = 10;
+const VOICE_COLORS: [&str; 6] = ["#ff9030", "#40c0ff", "#80ff80", "#ff60c0", "#ffff80", "#c080ff"];
+
+fn minutes(n: i64) -> Duration {
+    Duration::try_minutes(n).unwrap_or_default()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn header(svg: &mut String, width: f64, height: f64) {
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif">"#
+    );
+    let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="{BACKGROUND}"/>"#);
+}
+
+fn text(svg: &mut String, x: f64, y: f64, size: f64, color: &str, anchor: &str, content: &str) {
+    let _ = writeln!(
+        svg,
+        r#"<text x="{x:.1}" y="{y:.1}" font-size="{size}" fill="{color}" text-anchor="{anchor}">{}</text>"#,
+        escape(content)
+    );
+}
+
+// Image coordinates of a position on the sky
+fn map_point(position: SkyPosition) -> (f64, f64) {
+    let (x, y) = skyview::mollweide(position);
+    (MAP_X + (x + 1.0) / 2.0 * MAP_WIDTH, MAP_Y + (1.0 - y) / 2.0 * MAP_HEIGHT)
+}
+
+fn density_color(density: f64) -> &'static str {
+    DENSITY_COLORS.iter().find(|(min, _)| density >= *min).map_or(FAINT_COLOR, |(_, c)| c)
+}
+
+fn skymap_layer(svg: &mut String, skymap: &Skymap) {
+    let (cell_w, cell_h) = (MAP_WIDTH / CELLS_X as f64, MAP_HEIGHT / CELLS_Y as f64);
+    let peak = skymap.probability_density_at(skymap.max_probability()).max(f64::MIN_POSITIVE);
+
+    // Credible level bucket and color of every cell, None outside the map
+    let cells: Vec<Vec<Option<(usize, &str)>>> = (0..CELLS_Y)
+        .map(|j| {
+            (0..CELLS_X)
+                .map(|i| {
+                    let x = (i as f64 + 0.5) / CELLS_X as f64 * 2.0 - 1.0;
+                    let y = 1.0 - (j as f64 + 0.5) / CELLS_Y as f64 * 2.0;
+                    let position = skyview::inverse_mollweide(x, y)?;
+                    let bucket = skyview::bucket(skymap.credible_level_at(position));
+                    let density = skymap.probability_density_at(position) / peak;
+                    Some((bucket, density_color(density)))
+                })
+                .collect()
+        })
+        .collect();
+
+    // Runs of equally colored cells inside the outer region
+    for (j, row) in cells.iter().enumerate() {
+        let mut i = 0;
+        while i < CELLS_X {
+            let Some((bucket, color)) = row[i].filter(|(b, _)| *b < CREDIBLE_LEVELS.len()) else {
+                i += 1;
+                continue;
+            };
+            let start = i;
+            while i < CELLS_X && row[i] == Some((bucket, color)) {
+                i += 1;
+            }
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{color}"/>"#,
+                MAP_X + start as f64 * cell_w,
+                MAP_Y + j as f64 * cell_h,
+                (i - start) as f64 * cell_w + 0.3,
+                cell_h + 0.3
+            );
+        }
+    }
+
+    // Contours on the edges between cells of different buckets
+    let bucket = |i: usize, j: usize| cells[j][i].map(|(b, _)| b);
+    let mut path = String::new();
+    for j in 0..CELLS_Y {
+        for i in 0..CELLS_X {
+            let (x, y) = (MAP_X + i as f64 * cell_w, MAP_Y + j as f64 * cell_h);
+            let Some(here) = bucket(i, j) else {
+                continue;
+            };
+            if i + 1 < CELLS_X && bucket(i + 1, j).is_some_and(|b| b != here) {
+                let _ = write!(path, "M{:.1} {:.1}v{:.1}", x + cell_w, y, cell_h);
+            }
+            if j + 1 < CELLS_Y && bucket(i, j + 1).is_some_and(|b| b != here) {
+                let _ = write!(path, "M{:.1} {:.1}h{:.1}", x, y + cell_h, cell_w);
+            }
+        }
+    }
+    let _ = writeln!(svg, r#"<path d="{path}" stroke="{FOREGROUND}" stroke-width="1.2"/>"#);
+
+    let (x, y) = map_point(skymap.max_probability());
+    let _ = writeln!(
+        svg,
+        r#"<path d="M{:.1} {y:.1}h16M{x:.1} {:.1}v16" stroke="white" stroke-width="2"/>"#,
+        x - 8.0,
+        y - 8.0
+    );
+}
+
+// Ellipse, graticule and the detectors at their zenith when the wave passed
+fn map_frame(svg: &mut String, event: &GWEvent) {
+    let (cx, cy) = (MAP_X + MAP_WIDTH / 2.0, MAP_Y + MAP_HEIGHT / 2.0);
+    for dec in [-60.0, -30.0, 0.0, 30.0, 60.0] {
+        let (x0, y) = map_point(SkyPosition { ra: 359.999, dec });
+        let (x1, _) = map_point(SkyPosition { ra: 0.0, dec });
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{x0:.1}" y1="{y:.1}" x2="{x1:.1}" y2="{y:.1}" stroke="{DIM}" stroke-width="0.5"/>"#
+        );
+    }
+    for ra in (0..12).map(|h| h as f64 * 30.0) {
+        let points: Vec<String> = (-90..=90)
+            .step_by(5)
+            .map(|dec| {
+                let (x, y) = map_point(SkyPosition { ra, dec: dec as f64 });
+                format!("{x:.1},{y:.1}")
+            })
+            .collect();
+        let _ = writeln!(
+            svg,
+            r#"<polyline points="{}" fill="none" stroke="{DIM}" stroke-width="0.5"/>"#,
+            points.join(" ")
+        );
+    }
+    let _ = writeln!(
+        svg,
+        r#"<ellipse cx="{cx}" cy="{cy}" rx="{}" ry="{}" fill="none" stroke="{FOREGROUND}"/>"#,
+        MAP_WIDTH / 2.0,
+        MAP_HEIGHT / 2.0
+    );
+    let below = MAP_Y + MAP_HEIGHT + 18.0;
+    text(svg, MAP_X, below, 13.0, DIM, "start", "24h");
+    text(svg, cx, below, 13.0, DIM, "middle", "12h");
+    text(svg, MAP_X + MAP_WIDTH, below, 13.0, DIM, "end", "0h");
+
+    let gmst = observer::gmst(event.time);
+    for detector in DETECTORS.iter().filter(|d| event.detectors.iter().any(|n| n == d.name)) {
+        let zenith = SkyPosition { ra: (detector.lon + gmst).rem_euclid(360.0), dec: detector.lat };
+        let (x, y) = map_point(zenith);
+        let _ = writeln!(svg, r#"<circle cx="{x:.1}" cy="{y:.1}" r="4" fill="{DETECTOR_COLOR}"/>"#);
+        text(svg, x + 7.0, y + 5.0, 14.0, DETECTOR_COLOR, "start", detector.name);
+    }
+}
+
+fn bar(svg: &mut String, y: f64, label: &str, value: f64, color: &str) {
+    let (x, width) = (MAP_X + 130.0, 300.0);
+    text(svg, MAP_X, y + 12.0, 14.0, FOREGROUND, "start", label);
+    let _ = writeln!(
+        svg,
+        r#"<rect x="{x}" y="{y}" width="{width}" height="14" fill="none" stroke="{DIM}"/><rect x="{x}" y="{y}" width="{:.1}" height="14" fill="{color}"/>"#,
+        width * value.clamp(0.0, 1.0)
+    );
+    text(
+        svg,
+        x + width + 10.0,
+        y + 12.0,
+        14.0,
+        FOREGROUND,
+        "start",
+        &format!("{:.0}%", value * 100.0),
+    );
+}
+
+fn distance_bar(svg: &mut String, y: f64, event: &GWEvent) {
+    let (x, width) = (MAP_X + 530.0, 270.0);
+    // No skymap, no distance
+    if event.distance_mpc <= 0.0 {
+        text(svg, x, y - 8.0, 14.0, FOREGROUND, "start", "Distance");
+        text(svg, x, y + 14.0, 14.0, DIM, "start", "unknown");
+        return;
+    }
+    let lower = event.distance_percentile_mpc(0.05).max(0.0);
+    let upper = event.distance_percentile_mpc(0.95);
+    let scale = width / (upper * 1.1).max(1.0);
+
+    text(svg, x, y - 8.0, 14.0, FOREGROUND, "start", "Distance");
+    let _ = writeln!(
+        svg,
+        r#"<line x1="{x}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="{DIM}"/>"#,
+        y + 10.0,
+        x + width,
+        y + 10.0
+    );
+    let _ = writeln!(
+        svg,
+        r##"<rect x="{:.1}" y="{y}" width="{:.1}" height="20" fill="#40c0ff" fill-opacity="0.4"/>"##,
+        x + lower * scale,
+        (upper - lower) * scale
+    );
+    let _ = writeln!(
+        svg,
+        r#"<line x1="{0:.1}" y1="{y}" x2="{0:.1}" y2="{1:.1}" stroke="white" stroke-width="2"/>"#,
+        x + event.distance_mpc * scale,
+        y + 20.0
+    );
+    let label = format!(
+        "{:.0} Mpc (90%: {:.0}–{:.0}), z={:.3}",
+        event.distance_mpc,
+        lower,
+        upper,
+        event.redshift()
+    );
+    text(svg, x, y + 40.0, 14.0, FOREGROUND, "start", &label);
+}
+
+/// The image of `event`, with its skymap if there is one.
+pub fn event_svg(event: &GWEvent, skymap: Option<&Skymap>) -> String {
+    let mut svg = String::new();
+    header(&mut svg, WIDTH, HEIGHT);
+
+    text(&mut svg, MAP_X, 40.0, 28.0, "white", "start", &event.id);
+    let when =
+        format!("{} – {}", event.time.format("%Y-%m-%d %H:%M:%S UTC"), event.detectors.join(" "));
+    text(&mut svg, MAP_X + MAP_WIDTH, 40.0, 16.0, FOREGROUND, "end", &when);
+
+    match skymap {
+        Some(skymap) => skymap_layer(&mut svg, skymap),
+        None => text(
+            &mut svg,
+            MAP_X + MAP_WIDTH / 2.0,
+            MAP_Y + MAP_HEIGHT / 2.0,
+            18.0,
+            DIM,
+            "middle",
+            "no skymap",
+        ),
+    }
+    map_frame(&mut svg, event);
+    if let Some(area) = skymap.map(|s| s.credible_area_deg2(CREDIBLE_LEVELS[1])) {
+        let label = format!("90% credible region {:.0} deg²", area);
+        text(&mut svg, MAP_X + MAP_WIDTH, 62.0, 14.0, FOREGROUND, "end", &label);
+    }
+
+    let top = MAP_Y + MAP_HEIGHT + 50.0;
+    let classes = [
+        ("BNS", event.ns_ns, "#80ff80"),
+        ("NSBH", event.ns_bh, "#ffff80"),
+        ("BBH", event.bh_bh, "#ff9030"),
+        ("Terrestrial", event.terrestrial, DIM),
+        ("Has NS", event.has_ns, "#40c0ff"),
+        ("Has remnant", event.has_remnant, "#c080ff"),
+    ];
+    for (k, (label, value, color)) in classes.iter().enumerate() {
+        bar(&mut svg, top + k as f64 * 26.0, label, *value, color);
+    }
+    distance_bar(&mut svg, top + 20.0, event);
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Writes the image of `event` as `<name>.svg`, with the skymap from the
+/// cache folder if it was downloaded.
+pub fn export_event(dir: &Path, name: &str, event: &GWEvent, cache_dir: &Path) -> io::Result<()> {
+    let skymap = Skymap::read(&datafetch::skymap_path(cache_dir, &event.id)).ok();
+    fs::create_dir_all(dir)?;
+    cache::write_atomically(
+        &dir.join(format!("{name}.svg")),
+        event_svg(event, skymap.as_ref()).as_bytes(),
+    )
+}
+
+/// Writes the image of every event under its id.
+pub fn export_events(dir: &Path, events: &[GWEvent], cache_dir: &Path) -> io::Result<()> {
+    for event in events {
+        export_event(dir, &event.id, event, cache_dir)?;
+    }
+    Ok(())
+}
+
+/// What the voices played lately and will play next.
+#[derive(Default)]
+pub struct Timeline {
+    // Voice and start to end
+    spans: HashMap<(String, DateTime<Local>), DateTime<Local>>,
+}
+
+impl Timeline {
+    /// Adds what is playing now and forgets what left the window.
+    pub fn update<I>(&mut self, playing: I, now: DateTime<Local>)
+    where
+        I: IntoIterator<Item = (String, DateTime<Local>, DateTime<Local>)>,
+    {
+        for (voice, from, until) in playing {
+            self.spans.insert((voice, from), until);
+        }
+        let oldest = now - minutes(TIMELINE_WINDOW_MINUTES);
+        self.spans.retain(|_, until| *until > oldest);
+    }
+
+    pub fn svg(&self, now: DateTime<Local>) -> String {
+        let window = minutes(TIMELINE_WINDOW_MINUTES);
+        let (left, right, top) = (140.0, WIDTH - 40.0, 70.0);
+        let x = |time: DateTime<Local>| {
+            let offset = (time - (now - window)).num_milliseconds() as f64;
+            let span = (window * 2).num_milliseconds() as f64;
+            (left + offset / span * (right - left)).clamp(left, right)
+        };
+
+        let mut voices: Vec<&String> = self.spans.keys().map(|(voice, _)| voice).collect();
+        voices.sort();
+        voices.dedup();
+        let row_height = ((HEIGHT - top - 60.0) / voices.len().max(1) as f64).min(40.0);
+
+        let mut svg = String::new();
+        header(&mut svg, WIDTH, HEIGHT);
+        text(&mut svg, left, 40.0, 24.0, "white", "start", "Voices");
+        let stamp = now.with_timezone(&Utc).format("%H:%M:%S UTC").to_string();
+        text(&mut svg, right, 40.0, 16.0, FOREGROUND, "end", &stamp);
+
+        for (row, voice) in voices.iter().enumerate() {
+            let y = top + row as f64 * row_height;
+            let color = VOICE_COLORS[row % VOICE_COLORS.len()];
+            text(&mut svg, left - 10.0, y + row_height * 0.65, 14.0, FOREGROUND, "end", voice);
+            for ((_, from), until) in self.spans.iter().filter(|((v, _), _)| v == *voice) {
+                let _ = writeln!(
+                    svg,
+                    r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{color}" rx="3"/>"#,
+                    x(*from),
+                    y + row_height * 0.15,
+                    (x(*until) - x(*from)).max(2.0),
+                    row_height * 0.7
+                );
+            }
+        }
+
+        let bottom = top + voices.len() as f64 * row_height;
+        for offset in (-TIMELINE_WINDOW_MINUTES..=TIMELINE_WINDOW_MINUTES).step_by(5) {
+            let tick = x(now + minutes(offset));
+            let (color, label) = match offset {
+                0 => ("white", "now".to_string()),
+                m => (DIM, format!("{m:+} min")),
+            };
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{tick:.1}" y1="{top}" x2="{tick:.1}" y2="{bottom:.1}" stroke="{color}"/>"#
+            );
+            text(&mut svg, tick, bottom + 20.0, 13.0, color, "middle", &label);
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Rewrites the timeline in `dir`. It moves on every second, so it is not
+    /// synced to disk.
+    pub fn export(&self, dir: &Path, now: DateTime<Local>) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{TIMELINE}.svg"));
+        cache::write_atomically_unsynced(&path, self.svg(now).as_bytes())
+    }
+}
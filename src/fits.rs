@@ -0,0 +1,233 @@
+// Just enough of FITS to walk the header and data units of a skymap without
+// trusting the file: every size the headers claim is checked against the
+// bytes that are there. See https://fits.gsfc.nasa.gov/fits_standard.html
+
+// Files are made of blocks of 36 header cards of 80 characters
+const BLOCK: usize = 2880;
+const CARD: usize = 80;
+
+/// One header and data unit of a FITS file.
+pub struct Hdu<'a> {
+    // Keyword and value of every card that has one, quotes removed
+    cards: Vec<(String, String)>,
+    /// Data of the unit, without the padding to a whole block
+    pub data: &'a [u8],
+    // Header and data with their padding, what CHECKSUM covers
+    unit: &'a [u8],
+    // Data with its padding, what DATASUM covers
+    padded_data: &'a [u8],
+}
+
+impl Hdu<'_> {
+    /// Value of `key`, with the quotes and trailing blanks of strings removed.
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.cards.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn integer(&self, key: &str) -> Option<i64> {
+        self.value(key)?.parse().ok()
+    }
+
+    /// Value of `key` as a number, also with a Fortran style D exponent.
+    pub fn float(&self, key: &str) -> Option<f64> {
+        self.value(key)?.replace('D', "E").parse().ok()
+    }
+
+    /// Checks the CHECKSUM and DATASUM keywords of the unit, if it has them.
+    pub fn verify_checksums(&self) -> Result<(), String> {
+        if let Some(datasum) = self.value("DATASUM") {
+            let expected: u32 =
+                datasum.parse().map_err(|_| format!("invalid DATASUM {}", datasum))?;
+            let actual = ones_complement_sum(self.padded_data);
+            if actual != expected {
+                return Err(format!("DATASUM is {} but the data sums to {}", expected, actual));
+            }
+        }
+        // The CHECKSUM card is chosen to make the whole unit sum to -0
+        if self.value("CHECKSUM").is_some() && ones_complement_sum(self.unit) != u32::MAX {
+            return Err("CHECKSUM does not match".to_string());
+        }
+        Ok(())
+    }
+}
+
+// 32 bit ones' complement sum of the big endian words of `bytes`
+fn ones_complement_sum(bytes: &[u8]) -> u32 {
+    let mut sum: u64 = bytes
+        .chunks(4)
+        .map(|word| {
+            let mut padded = [0; 4];
+            padded[..word.len()].copy_from_slice(word);
+            u64::from(u32::from_be_bytes(padded))
+        })
+        .sum();
+    while sum >> 32 != 0 {
+        sum = (sum & 0xffff_ffff) + (sum >> 32);
+    }
+    sum as u32
+}
+
+// Keyword and value of a header card, None for comments and blank cards
+fn parse_card(card: &[u8]) -> Result<Option<(String, String)>, String> {
+    let card = std::str::from_utf8(card).map_err(|_| "header is not ASCII".to_string())?;
+    if !card.is_ascii() {
+        return Err("header is not ASCII".to_string());
+    }
+    let key = card[..8].trim_end().to_string();
+    if &card[8..10] != "= " {
+        return Ok(None);
+    }
+    let value = card[10..].trim_start();
+    let value = match value.strip_prefix('\'') {
+        // Quotes inside strings are doubled
+        Some(quoted) => {
+            let mut string = String::new();
+            let mut chars = quoted.chars();
+            loop {
+                match chars.next() {
+                    Some('\'') if chars.clone().next() == Some('\'') => {
+                        chars.next();
+                        string.push('\'');
+                    }
+                    Some('\'') => break,
+                    Some(c) => string.push(c),
+                    None => return Err(format!("unterminated string in {}", key)),
+                }
+            }
+            string.trim_end().to_string()
+        }
+        None => value.split('/').next().unwrap_or("").trim().to_string(),
+    };
+    Ok(Some((key, value)))
+}
+
+/// Splits a FITS file into its header and data units.
+pub fn hdus(bytes: &[u8]) -> Result<Vec<Hdu<'_>>, String> {
+    let mut hdus = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() {
+        let mut cards = Vec::new();
+        let mut position = start;
+        loop {
+            let Some(card) = bytes.get(position..position + CARD) else {
+                return Err(format!("header at byte {} has no END", start));
+            };
+            position += CARD;
+            if card.starts_with(b"END") && card[3..].iter().all(|c| *c == b' ') {
+                break;
+            }
+            cards.extend(parse_card(card)?);
+        }
+        let data_start = position.next_multiple_of(BLOCK);
+
+        let mut hdu = Hdu { cards, data: &[], unit: &[], padded_data: &[] };
+        let length =
+            data_length(&hdu).ok_or_else(|| format!("header at byte {} has no size", start))?;
+        let data_end = data_start
+            .checked_add(length)
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(|| format!("data at byte {} is truncated", data_start))?;
+        let end = data_end.next_multiple_of(BLOCK).min(bytes.len());
+        hdu.data = &bytes[data_start..data_end];
+        hdu.padded_data = &bytes[data_start..end];
+        hdu.unit = &bytes[start..end];
+        hdus.push(hdu);
+        start = end;
+    }
+    if hdus.is_empty() {
+        return Err("file is empty".to_string());
+    }
+    Ok(hdus)
+}
+
+// Bytes of data the header announces
+fn data_length(hdu: &Hdu) -> Option<usize> {
+    let bytes_per_value = usize::try_from(hdu.integer("BITPIX")?.unsigned_abs() / 8).ok()?;
+    let naxis = hdu.integer("NAXIS")?;
+    if naxis == 0 {
+        return Some(0);
+    }
+    let mut values: usize = 1;
+    for i in 1..=naxis {
+        values = values.checked_mul(usize::try_from(hdu.integer(&format!("NAXIS{}", i))?).ok()?)?;
+    }
+    // Binary tables keep a heap of PCOUNT bytes after the rows
+    let pcount = usize::try_from(hdu.integer("PCOUNT").unwrap_or(0)).ok()?;
+    let gcount = usize::try_from(hdu.integer("GCOUNT").unwrap_or(1)).ok()?;
+    values.checked_add(pcount)?.checked_mul(gcount)?.checked_mul(bytes_per_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(cards: &[&str]) -> Vec<u8> {
+        let mut out: Vec<u8> =
+            cards.iter().flat_map(|card| format!("{:<80}", card).into_bytes()).collect();
+        out.extend(format!("{:<80}", "END").into_bytes());
+        out.resize(out.len().next_multiple_of(BLOCK), b' ');
+        out
+    }
+
+    fn table(extra: &[&str]) -> Vec<u8> {
+        let mut file = block(&[
+            "SIMPLE  =                    T",
+            "BITPIX  =                    8",
+            "NAXIS   =                    0",
+        ]);
+        let mut cards = vec![
+            "XTENSION= 'BINTABLE'           / binary table",
+            "BITPIX  =                    8",
+            "NAXIS   =                    2",
+            "NAXIS1  =                    8",
+            "NAXIS2  =                    3",
+            "ORDERING= 'NUNIQ   '",
+            "DISTMEAN=    440.2163925170898 / Posterior mean distance (Mpc)",
+            "DISTSTD =              1.7D+02",
+            "COMMENT it's a test",
+        ];
+        cards.extend_from_slice(extra);
+        file.extend(block(&cards));
+        let data_start = file.len();
+        for uniq in [4u64, 5, 6] {
+            file.extend(uniq.to_be_bytes());
+        }
+        file.resize(data_start + BLOCK, 0);
+        file
+    }
+
+    #[test]
+    fn reads_headers_and_data() {
+        let file = table(&[]);
+        let hdus = hdus(&file).unwrap();
+        assert_eq!(hdus.len(), 2);
+        assert_eq!(hdus[0].data.len(), 0);
+        assert_eq!(hdus[1].value("XTENSION"), Some("BINTABLE"));
+        assert_eq!(hdus[1].value("ORDERING"), Some("NUNIQ"));
+        assert_eq!(hdus[1].integer("NAXIS2"), Some(3));
+        assert_eq!(hdus[1].float("DISTMEAN"), Some(440.2163925170898));
+        assert_eq!(hdus[1].float("DISTSTD"), Some(170.0));
+        assert_eq!(hdus[1].float("ORDERING"), None);
+        assert_eq!(hdus[1].data.len(), 24);
+        assert_eq!(hdus[1].data[8..16], 5u64.to_be_bytes());
+        assert!(hdus[1].verify_checksums().is_ok());
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let file = table(&[]);
+        let truncated = &file[..file.len() - BLOCK + 16];
+        assert!(hdus(truncated).err().unwrap().contains("truncated"));
+        assert!(hdus(&file[..BLOCK + 400]).err().unwrap().contains("no END"));
+        assert!(hdus(&[]).is_err());
+    }
+
+    #[test]
+    fn checks_datasum() {
+        // 4 + 5 + 6 in the low words
+        let file = table(&["DATASUM = '15      '"]);
+        assert!(hdus(&file).unwrap()[1].verify_checksums().is_ok());
+        let file = table(&["DATASUM = '16      '"]);
+        assert!(hdus(&file).unwrap()[1].verify_checksums().is_err());
+    }
+}
@@ -0,0 +1,69 @@
+// Layout of a fixture directory mirroring the GraceDB REST API.
+//
+//   superevents/index@query=<query>.json     the superevents list
+//   superevents/<id>/files/index.json        the files map of an event
+//   superevents/<id>/files/<name>            a single file
+//
+// The `api/` or `apiweb/` prefix and the host are dropped, so that links
+// inside the recorded JSON can be pointed to any other server.
+
+use std::path::{Path, PathBuf};
+
+use reqwest::Url;
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.,=".contains(c) { c } else { '_' })
+        .collect()
+}
+
+/// File inside `root` that holds the response for `url`.
+pub fn fixture_path(root: &Path, url: &Url) -> PathBuf {
+    let mut path = root.to_path_buf();
+
+    let segments: Vec<String> =
+        url.path().split('/').filter(|s| !s.is_empty()).map(percent_decode).collect();
+    let skip = match segments.first().map(String::as_str) {
+        Some("api") | Some("apiweb") => 1,
+        _ => 0,
+    };
+
+    let query: Vec<String> =
+        url.query_pairs().map(|(k, v)| sanitize(&format!("{k}={v}"))).collect();
+    let suffix = if query.is_empty() { String::new() } else { format!("@{}", query.join("&")) };
+
+    for segment in &segments[skip..] {
+        // Never leave the fixture directory
+        if segment != ".." {
+            path.push(sanitize(segment));
+        }
+    }
+
+    if url.path().ends_with('/') || segments.len() <= skip {
+        path.push(format!("index{suffix}.json"));
+    } else if !suffix.is_empty() {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        path.set_file_name(format!("{name}{suffix}"));
+    }
+
+    path
+}
@@ -0,0 +1,209 @@
+// HEALPix pixelisation in the NESTED scheme, as used by the NUNIQ indexed
+// multiorder skymaps. Follows Górski et al. 2005 and the reference
+// implementation in healpix_base.
+
+use std::f64::consts::{FRAC_PI_2, PI};
+
+const JRLL: [i64; 12] = [2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4];
+const JPLL: [i64; 12] = [1, 3, 5, 7, 0, 2, 4, 6, 1, 3, 5, 7];
+
+pub fn nside(order: u8) -> u64 {
+    1 << order
+}
+
+pub fn npix(order: u8) -> u64 {
+    12 * nside(order) * nside(order)
+}
+
+/// Area of a single pixel in steradians.
+pub fn pixel_area(order: u8) -> f64 {
+    4.0 * PI / npix(order) as f64
+}
+
+/// Combines order and nested pixel index into a NUNIQ index.
+pub fn uniq(order: u8, ipix: u64) -> u64 {
+    4 * (1 << (2 * order as u64)) + ipix
+}
+
+// Takes every other bit, starting with the lowest one
+fn compress_bits(mut v: u64) -> u64 {
+    let mut result = 0;
+    let mut bit = 0;
+    while v != 0 {
+        result |= (v & 1) << bit;
+        v >>= 2;
+        bit += 1;
+    }
+    result
+}
+
+/// Colatitude and longitude (theta, phi) in radians of the center of a pixel.
+pub fn nest2ang(order: u8, ipix: u64) -> (f64, f64) {
+    let nside = nside(order) as i64;
+    let npface = nside * nside;
+    let ipix = ipix as i64;
+
+    let face = (ipix / npface) as usize;
+    let ipf = (ipix % npface) as u64;
+    let ix = compress_bits(ipf) as i64;
+    let iy = compress_bits(ipf >> 1) as i64;
+
+    let nl4 = 4 * nside;
+    let fact2 = 4.0 / npix(order) as f64;
+    let fact1 = (2 * nside) as f64 * fact2;
+
+    let jr = JRLL[face] * nside - ix - iy - 1;
+
+    let (nr, z, kshift) = if jr < nside {
+        (jr, 1.0 - (jr * jr) as f64 * fact2, 0)
+    } else if jr > 3 * nside {
+        let nr = nl4 - jr;
+        (nr, (nr * nr) as f64 * fact2 - 1.0, 0)
+    } else {
+        (nside, (2 * nside - jr) as f64 * fact1, (jr - nside) & 1)
+    };
+
+    let mut jp = (JPLL[face] * nr + ix - iy + 1 + kshift) / 2;
+    if jp > nl4 {
+        jp -= nl4;
+    }
+    if jp < 1 {
+        jp += nl4;
+    }
+
+    let phi = (jp as f64 - (kshift + 1) as f64 * 0.5) * (FRAC_PI_2 / nr as f64);
+    (z.clamp(-1.0, 1.0).acos(), phi)
+}
+
+/// Splits a NUNIQ index into order and nested pixel index.
+pub fn uniq2nest(uniq: u64) -> Option<(u8, u64)> {
+    if uniq < 4 {
+        return None;
+    }
+    // uniq = 4 * 4^order + ipix with ipix < 12 * 4^order
+    let order = ((63 - uniq.leading_zeros()) / 2 - 1) as u8;
+    Some((order, uniq - 4 * (1 << (2 * order as u64))))
+}
+
+// Inverse of compress_bits
+fn spread_bits(mut v: u64) -> u64 {
+    let mut result = 0;
+    let mut bit = 0;
+    while v != 0 {
+        result |= (v & 1) << (2 * bit);
+        v >>= 1;
+        bit += 1;
+    }
+    result
+}
+
+/// Nested index of the pixel containing colatitude `theta` and longitude
+/// `phi` in radians.
+pub fn ang2nest(order: u8, theta: f64, phi: f64) -> u64 {
+    let nside = nside(order) as i64;
+    let z = theta.cos();
+    let za = z.abs();
+    // In [0, 4), one unit per base pixel column
+    let tt = phi.rem_euclid(2.0 * PI) / FRAC_PI_2;
+
+    let (face, ix, iy) = if za <= 2.0 / 3.0 {
+        // Equatorial region
+        let temp1 = nside as f64 * (0.5 + tt);
+        let temp2 = nside as f64 * (z * 0.75);
+        let jp = (temp1 - temp2) as i64; // index of ascending edge line
+        let jm = (temp1 + temp2) as i64; // index of descending edge line
+        let ifp = jp / nside;
+        let ifm = jm / nside;
+        let face = if ifp == ifm {
+            ifp | 4
+        } else if ifp < ifm {
+            ifp
+        } else {
+            ifm + 8
+        };
+        (face, jm & (nside - 1), nside - (jp & (nside - 1)) - 1)
+    } else {
+        // Polar caps
+        let ntt = (tt as i64).min(3);
+        let tp = tt - ntt as f64;
+        // sin(theta) is more precise than 1 - |z| close to the poles
+        let tmp = nside as f64 * theta.sin() * (3.0 / (1.0 + za)).sqrt();
+        let jp = ((tp * tmp) as i64).min(nside - 1);
+        let jm = (((1.0 - tp) * tmp) as i64).min(nside - 1);
+        if z >= 0.0 {
+            (ntt, nside - jm - 1, nside - jp - 1)
+        } else {
+            (ntt + 8, jp, jm)
+        }
+    };
+
+    face as u64 * (nside * nside) as u64 + spread_bits(ix as u64) + (spread_bits(iy as u64) << 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn base_pixel_centers() {
+        // The twelve base pixels: four around each pole, four on the equator
+        let (theta, phi) = nest2ang(0, 0);
+        assert_close(theta, (2.0f64 / 3.0).acos());
+        assert_close(phi, PI / 4.0);
+        let (theta, phi) = nest2ang(0, 4);
+        assert_close(theta, FRAC_PI_2);
+        assert_close(phi, 0.0);
+        let (theta, phi) = nest2ang(0, 11);
+        assert_close(theta, (-2.0f64 / 3.0).acos());
+        assert_close(phi, 7.0 * PI / 4.0);
+    }
+
+    #[test]
+    fn ang2nest_inverts_nest2ang() {
+        for order in [0, 1, 3, 6] {
+            for ipix in 0..npix(order) {
+                let (theta, phi) = nest2ang(order, ipix);
+                assert_eq!(ang2nest(order, theta, phi), ipix, "order {}", order);
+            }
+        }
+    }
+
+    #[test]
+    fn uniq_known_values() {
+        // 4 * 4^order is the first index of every order
+        assert_eq!(uniq2nest(4), Some((0, 0)));
+        assert_eq!(uniq2nest(15), Some((0, 11)));
+        assert_eq!(uniq2nest(16), Some((1, 0)));
+        assert_eq!(uniq2nest(63), Some((1, 47)));
+        assert_eq!(uniq2nest(64), Some((2, 0)));
+        assert_eq!(uniq2nest(uniq(29, npix(29) - 1)), Some((29, npix(29) - 1)));
+        assert_eq!(uniq2nest(3), None);
+    }
+
+    #[test]
+    fn ang2nest_known_values() {
+        // The poles are the northern corner of faces 0 to 3 and the southern
+        // corner of faces 8 to 11
+        assert_eq!(ang2nest(0, 0.0, 0.0), 0);
+        assert_eq!(ang2nest(1, 0.0, 0.0), 3);
+        assert_eq!(ang2nest(1, PI, 0.0), 32);
+        assert_eq!(ang2nest(4, PI, 0.0), 8 * 256);
+        // Around the center of face 4: south, east, west and north quarter
+        assert_eq!(ang2nest(1, FRAC_PI_2 + 0.01, 0.0), 16);
+        assert_eq!(ang2nest(1, FRAC_PI_2, 0.01), 17);
+        assert_eq!(ang2nest(1, FRAC_PI_2, -0.01), 18);
+        assert_eq!(ang2nest(1, FRAC_PI_2 - 0.01, 0.0), 19);
+        // Longitudes wrap around
+        assert_eq!(ang2nest(3, 1.0, 2.0 * PI + 0.5), ang2nest(3, 1.0, 0.5));
+    }
+
+    #[test]
+    fn pixels_cover_the_sphere() {
+        assert_eq!(npix(3), 768);
+        assert_close(pixel_area(3) * npix(3) as f64, 4.0 * PI);
+    }
+}
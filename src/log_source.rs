@@ -43,10 +43,10 @@ where
                 println!("{:?}", value);
                 self.max = Some(value);
             }
-            return Some(value);
+            Some(value)
         } else {
             println!("Finished source {:?}", self.log);
-            return None;
+            None
         }
     }
 
@@ -1,27 +1,54 @@
+mod ambisonics;
+mod blur;
+mod cache;
+mod catalog;
 mod datafetch;
+mod detectors;
+mod distance;
+mod export;
+mod fits;
+mod fixtures;
+mod healpix;
 mod log_source;
+mod noise;
+mod observer;
+mod physics;
+mod recorder;
 mod sine_beat;
+mod skymap;
+mod skyview;
+mod spatial;
+mod synthetic;
 mod take_with_fade;
+mod timbre;
+mod timelapse;
 mod triangle_wave;
 
 use std::fmt::Debug;
-use std::fs::{self, File};
-use std::io::{BufReader, BufWriter};
+#[cfg(not(feature = "generate_tones"))]
+use std::fs::File;
+#[cfg(not(feature = "generate_tones"))]
+use std::io::BufReader;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use chrono::{DateTime, Local};
-use clap::Parser;
+use chrono::{DateTime, Local, Utc};
+use clap::{Parser, Subcommand};
 use colored::Colorize;
 use dashmap::DashMap;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
 use rand::Rng;
 use rodio::queue::{queue, SourcesQueueInput};
 use rodio::source::Source;
-use rodio::{dynamic_mixer, OutputStream, Sample, Sink};
+use rodio::{dynamic_mixer, Sample, Sink};
 
-use crate::datafetch::read_gracedb;
+use crate::cache::{read_cache, read_or_renew_cache, CacheSource, EVENTS_CACHE};
+use crate::datafetch::{
+    read_gracedb, FetchOptions, GraceDbClient, HttpMode, RetryPolicy, SUPEREVENT_QUERY,
+};
 use crate::take_with_fade::TakeWithFade;
 
 #[cfg(not(feature = "generate_tones"))]
@@ -57,8 +84,7 @@ fn source(str: &str) -> SourceOnce {
     println!("Max amplitude: {:?}", max.unwrap());
 
     let file = File::open(path.clone()).unwrap();
-    let data = rodio::Decoder::new(BufReader::new(file)).unwrap();
-    data
+    rodio::Decoder::new(BufReader::new(file)).unwrap()
 }
 
 // fn play_background {
@@ -100,8 +126,6 @@ M75 / M35
 
 */
 
-const EVENTS_CACHE: &str = "Events.json";
-
 trait SourceExt {
     #[inline]
     fn take_duration_with_fade(
@@ -129,12 +153,162 @@ fn sty(title: &str) -> ProgressStyle {
     //.progress_chars("##-")
 }
 
+fn parse_speedup(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(speedup) if speedup.is_finite() && speedup > 0.0 => Ok(speedup),
+        Ok(_) => Err("must be a positive number".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(secs) if Duration::try_from_secs_f64(secs).is_ok() => Ok(secs),
+        Ok(_) => Err("must be zero or a positive number of seconds".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_stretch(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(stretch) if stretch.is_finite() && stretch >= 0.0 => Ok(stretch),
+        Ok(_) => Err("must be zero or a positive number".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(long, default_value_t = false)]
     offline: bool,
 
+    /// GraceDB API root, e.g. http://127.0.0.1:8000/apiweb/ for gwrust-mockdb
+    #[arg(long, default_value = "https://gracedb.ligo.org/apiweb/")]
+    gracedb_url: String,
+
+    /// Store all responses from GraceDB in this folder
+    #[arg(long, conflicts_with = "replay_http")]
+    record_http: Option<PathBuf>,
+
+    /// Answer all requests to GraceDB from a folder written by --record-http
+    #[arg(long)]
+    replay_http: Option<PathBuf>,
+
+    /// How often a failed request to GraceDB is retried
+    #[arg(long, default_value_t = 3)]
+    http_retries: u32,
+
+    /// Timeout for a single request to GraceDB in seconds
+    #[arg(long, default_value_t = 30)]
+    http_timeout: u64,
+
+    /// Minimum time between two requests to GraceDB in seconds, once the
+    /// burst allowed by --http-burst is used up
+    #[arg(long, default_value_t = 0.5, value_parser = parse_seconds)]
+    http_min_interval: f64,
+
+    /// Number of requests to GraceDB that may start at once, enough for the
+    /// first fetch of 20 events
+    #[arg(long, default_value_t = 100)]
+    http_burst: u32,
+
+    /// Number of the most recent confirmed superevents to play
+    #[arg(long, default_value_t = 3)]
+    last_n: usize,
+
+    /// Number of events fetched from GraceDB in parallel
+    #[arg(long, default_value_t = 4)]
+    fetch_workers: usize,
+
+    /// Maximum number of pages of the superevents list to go through
+    #[arg(long, default_value_t = 5)]
+    max_pages: usize,
+
+    /// Folder for the events and skymaps, shared by all gwrust instances
+    /// [default: $XDG_CACHE_HOME/gwrust]
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// Size limit of the cache folder in megabytes
+    #[arg(long, default_value_t = 500)]
+    cache_max_mb: u64,
+
+    /// Downloads in the cache folder unused for this many days are removed
+    #[arg(long, default_value_t = 90)]
+    cache_max_age_days: u64,
+
+    /// Latitude of the installation in degrees, north positive. Without a
+    /// site, the events sound from all speakers alike
+    #[arg(long, requires = "site_lon", allow_hyphen_values = true)]
+    site_lat: Option<f64>,
+
+    /// Longitude of the installation in degrees, east positive
+    #[arg(long, requires = "site_lat", allow_hyphen_values = true)]
+    site_lon: Option<f64>,
+
+    /// JSON file with the compass directions of a speaker ring, one per
+    /// output channel, e.g. {"speakers": [0, 45, 90, 135, 180, 225, 270, 315]}
+    /// [default: stereo]
+    #[arg(long)]
+    speaker_layout: Option<PathBuf>,
+
+    /// Encode the mix into Ambisonics of this order (AmbiX: ACN, SN3D)
+    #[arg(long, conflicts_with = "speaker_layout", value_parser = clap::value_parser!(u8).range(1..=3))]
+    ambisonics: Option<u8>,
+
+    /// Write the mix to this WAV file instead of playing it, continued in
+    /// e.g. mix-2.wav before the file reaches the 4 GiB WAV limit
+    #[arg(long)]
+    output_wav: Option<PathBuf>,
+
+    /// Keep SVG images of the events and of the timeline of the voices in
+    /// this folder, e.g. for a projector
+    #[arg(long)]
+    export_dir: Option<PathBuf>,
+
+    /// Replay a GWTC catalog CSV from GWOSC instead of the live GraceDB events
+    #[arg(long)]
+    catalog: Option<PathBuf>,
+
+    /// Play this many generated events instead of the live GraceDB events
+    #[arg(long)]
+    synthetic: Option<usize>,
+
+    /// Seed for the generated events
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Also generate a multiorder skymap for every generated event, kept in
+    /// the gwrust-synthetic folder of the temporary directory
+    #[arg(long, default_value_t = false)]
+    synthetic_skymaps: bool,
+
+    /// Instead of the composition, replay the arrival times of the events
+    /// sped up by this factor (43200 plays one month per minute)
+    #[arg(long, value_parser = parse_speedup)]
+    time_lapse: Option<f64>,
+
+    /// Play one voice per detector, delayed by the difference in arrival time
+    /// of the wavefront stretched by this factor (50 turns 10 ms into 0.5 s)
+    #[arg(long, value_parser = parse_stretch)]
+    detector_delays: Option<f64>,
+
+    /// Distances in Mpc between the near, middle and far events. Near ones get
+    /// the 10 s Triangle 200/201 samples, middle ones the 5 s and far ones the
+    /// 2 s samples, each quieter than the last. Events of unknown distance get
+    /// the classic bursts of twelve 2 s cues
+    #[arg(long, value_parser = distance::parse_limits, default_value = "2000,4000")]
+    distance_bands: [f64; 2],
+
+    /// How many Triangle 200/201 cues each burst has for near, middle and far
+    /// events. The defaults keep every burst about as long as the classic one
+    #[arg(long, value_parser = distance::parse_repeats, default_value = "4,6,12")]
+    distance_repeats: [u32; 3],
+
     #[arg(long, default_value_t = false)]
     generate_tones: bool,
 
@@ -167,77 +341,52 @@ struct Args {
 
     #[arg(long, default_value_t = 0.05)]
     vol_m201: f32,
-}
-
-fn is_cache_valid(file_path: &str, duration: Duration) -> Result<bool, Box<dyn std::error::Error>> {
-    match fs::metadata(file_path) {
-        Ok(metadata) => {
-            let modified_time = metadata.modified()?;
-            let current_time = std::time::SystemTime::now();
 
-            let fail =
-                std::io::Error::new(std::io::ErrorKind::Other, "Failed to determine file age.")
-                    .into();
-
-            current_time
-                .duration_since(modified_time)
-                .map(|elapsed| elapsed < duration)
-                .map_err(|_err| fail)
-        }
-        // if fs::metadata errs then there is no file, hence no cache
-        Err(_) => Ok(false),
-    }
+    /// Volume of the detector voices of --detector-delays
+    #[arg(long, default_value_t = 0.05)]
+    vol_detectors: f32,
 }
 
-fn read_cache(path: &str) -> Result<datafetch::GWEventVec, Box<dyn std::error::Error>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let e = serde_json::from_reader(reader)?;
-    Ok(e)
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Summarize a multiorder skymap such as bayestar.multiorder.fits
+    Skymap {
+        path: PathBuf,
+
+        /// Right ascension in degrees to give the probability at
+        #[arg(long, requires = "dec", allow_hyphen_values = true)]
+        ra: Option<f64>,
+
+        /// Declination in degrees to give the probability at
+        #[arg(long, requires = "ra", allow_hyphen_values = true)]
+        dec: Option<f64>,
+
+        /// Credible levels to give the area of
+        #[arg(long, value_delimiter = ',', default_values_t = [0.5, 0.9])]
+        credible: Vec<f64>,
+    },
+
+    /// Look at the events without playing them
+    Events {
+        #[command(subcommand)]
+        command: EventsCommand,
+    },
 }
 
-fn write_to_cache(
-    data: &datafetch::GWEventVec,
-    path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let file = File::create(path)?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer(writer, data)?;
-    Ok(())
-}
+#[derive(Subcommand, Debug)]
+enum EventsCommand {
+    /// Fetch the events and skymaps into the cache folder and list them
+    Fetch,
 
-fn fetch_fn_to_cache<F>(f: F, path: &str) -> Result<datafetch::GWEventVec, Box<dyn std::error::Error>>
-where
-    F: FnOnce() -> Result<datafetch::GWEventVec, Box<dyn std::error::Error>>,
-{
-    let data = f()?;
-    write_to_cache( &data, path)?;
-    Ok(data)
-}
+    /// Draw the cached skymaps of all events, or of the one given, in the
+    /// terminal
+    Show {
+        id: Option<String>,
 
-fn read_or_renew_cache<F>(
-    path: &str,
-    duration: Duration,
-    f: F,
-) -> Result<datafetch::GWEventVec, Box<dyn std::error::Error>>
-where
-    F: FnOnce() -> Result<datafetch::GWEventVec, Box<dyn std::error::Error>>,
-{
-    let maybe_cached_value = read_cache(path);
-
-    let valid = is_cache_valid(path, duration).is_ok_and(|x| x == true);
-
-    match (maybe_cached_value, valid) {
-        (Ok(cached), true) => {
-            println!("Loading event data from cache file {path}.");
-            Ok(cached)
-        }
-        (Ok(cached), false) => fetch_fn_to_cache(f, path).or_else(|e| {
-            println!("Error updating cache: {:?}. Falling back to old version.", e);
-            Ok(cached)
-        }),
-        _ => fetch_fn_to_cache(f, path),
-    }
+        /// Width of the map in characters
+        #[arg(long, default_value_t = 80)]
+        width: usize,
+    },
 }
 
 fn main() {
@@ -247,26 +396,214 @@ fn main() {
     println!("==== {} ({}) ====", "GWrust".blue(), GIT_VERSION.white());
     println!();
 
+    if let Some(Command::Skymap { path, ra, dec, credible }) = &args.command {
+        let query = skymap::SkymapQuery {
+            path: path.clone(),
+            position: ra.zip(*dec).map(|(ra, dec)| skymap::SkyPosition { ra, dec }),
+            levels: credible.clone(),
+        };
+        if let Err(e) = skymap::describe(&query) {
+            println!("Could not read skymap: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let layout = match (&args.speaker_layout, args.ambisonics) {
+        (Some(path), _) => match spatial::Layout::read(path) {
+            Ok(layout) => layout,
+            Err(e) => {
+                println!("Could not read the speaker layout. Error {:?}.", e);
+                return;
+            }
+        },
+        (None, Some(order)) => spatial::Layout::Ambisonics(order),
+        (None, None) => spatial::Layout::Stereo,
+    };
+    let layout = Arc::new(layout);
+
     let ten_minutes = Duration::from_secs(600);
-    let last_n = 3;
 
-    let gw_events = if args.offline {
-        read_cache(EVENTS_CACHE)
-    } else {
-        read_or_renew_cache(EVENTS_CACHE, ten_minutes, || read_gracedb(last_n))
+    let http_mode = match (&args.record_http, &args.replay_http) {
+        (Some(dir), _) => HttpMode::Record(dir.clone()),
+        (_, Some(dir)) => HttpMode::Replay(dir.clone()),
+        _ => HttpMode::Live,
+    };
+    let retry = RetryPolicy {
+        max_retries: args.http_retries,
+        timeout: Duration::from_secs(args.http_timeout),
+        min_interval: Duration::from_secs_f64(args.http_min_interval),
+        burst: args.http_burst,
+        ..Default::default()
+    };
+    // Generated skymaps stay out of the shared cache
+    let cache_dir = match args.synthetic {
+        Some(_) => std::env::temp_dir().join("gwrust-synthetic"),
+        None => args.cache_dir.clone().unwrap_or_else(cache::default_cache_dir),
+    };
+    if args.cache_dir.is_none() && args.synthetic.is_none() {
+        if let Err(e) = cache::migrate_legacy_cache(&cache_dir) {
+            println!("Could not move the old cache to {:?}: {}", cache_dir, e);
+        }
+    }
+    let events_cache = cache_dir.join(EVENTS_CACHE);
+    let gracedb = match GraceDbClient::new(&args.gracedb_url, http_mode, retry, &cache_dir) {
+        Ok(gracedb) => gracedb,
+        Err(e) => {
+            println!("Could not set up the HTTP client. Error {:?}.", e);
+            return;
+        }
+    };
+    let cache_source =
+        CacheSource { url: gracedb.superevents_url(), query: SUPEREVENT_QUERY.to_string() };
+    let fetch_options = FetchOptions {
+        last_n: args.last_n,
+        workers: args.fetch_workers,
+        max_pages: args.max_pages,
     };
 
-    if let Ok(evs) = gw_events {
-        println!("Last {last_n} confirmed superevents:");
-        for ev in evs.iter() {
-            println!("{}", ev);
+    let evict = {
+        let cache_dir = cache_dir.clone();
+        let max_bytes = args.cache_max_mb.saturating_mul(1024 * 1024);
+        let max_age = Duration::from_secs(args.cache_max_age_days.saturating_mul(24 * 3600));
+        move |keep: &[datafetch::GWEvent]| {
+            if let Err(e) = datafetch::evict_cache(&cache_dir, keep, max_bytes, max_age) {
+                println!("Could not clean up cache: {}", e);
+            }
         }
-        println!();
+    };
+
+    // Fetches the events, again whenever a background renewal failed
+    let renew = {
+        let (gracedb, cache_dir, evict) = (Arc::new(gracedb), cache_dir.clone(), evict.clone());
+        move || {
+            let report = {
+                // Keeps other instances from evicting files while we fetch
+                let _cache_lock = cache::lock_cache_dir(&cache_dir);
+                read_gracedb(&gracedb, &fetch_options)
+            };
+
+            let mut report = report?;
+            for (id, e) in report.failures.iter() {
+                println!("{} {}: {}", "Warning: could not fetch".yellow(), id, e);
+            }
+            match report.failures.pop() {
+                // Keep the old cache rather than replacing it with nothing
+                Some((_, e)) if report.events.is_empty() => Err(e.into()),
+                _ => {
+                    // Every renewal downloads, so every renewal cleans up.
+                    // A failed one leaves everything for the next try.
+                    evict(&report.events);
+                    Ok(report.events)
+                }
+            }
+        }
+    };
+
+    // Showing the skymaps only reads what is in the cache
+    let show_only =
+        matches!(&args.command, Some(Command::Events { command: EventsCommand::Show { .. } }));
+
+    let gw_events = if let Some(catalog) = &args.catalog {
+        catalog::read_catalog(catalog).map(|evs| (evs, None))
+    } else if let Some(n) = args.synthetic {
+        // Keeps other instances from evicting the skymaps while we write them
+        let _cache_lock = cache::lock_cache_dir(&cache_dir);
+        synthetic::synthetic_events(args.seed, n, args.synthetic_skymaps, &cache_dir)
+            .map(|evs| (evs, None))
+    } else if args.offline || show_only {
+        read_cache(&events_cache).map(|cached| (cached.events, None))
     } else {
-        println!("Could not fetch events. Error {:?}.", gw_events);
+        read_or_renew_cache(&events_cache, ten_minutes, &cache_source, renew.clone())
+    };
+    // Cleaning up after GraceDB is left to `renew`, which knows whether the
+    // fetch worked. Offline, nothing is downloaded and nothing removed.
+    if let (Some(_), Ok((evs, _))) = (args.synthetic, &gw_events) {
+        evict(evs);
+    }
+
+    let (mut gw_events, mut revalidation) = match gw_events {
+        Ok((evs, _)) if args.catalog.is_some() => {
+            println!("Replaying {} catalog events:", evs.len());
+            (evs, None)
+        }
+        Ok((evs, _)) if args.synthetic.is_some() => {
+            println!("Playing {} synthetic events (seed {}):", evs.len(), args.seed);
+            (evs, None)
+        }
+        Ok((evs, revalidation)) => {
+            println!("Last {} confirmed superevents:", args.last_n);
+            (evs, revalidation)
+        }
+        Err(e) => {
+            println!("Could not fetch events. Error {:?}.", e);
+            return;
+        }
+    };
+    let site = args.site_lat.zip(args.site_lon).map(|(lat, lon)| observer::Site { lat, lon });
+    for ev in gw_events.iter() {
+        println!("{}", ev);
+        if let Some(horizontal) = site.and_then(|site| ev.horizontal(&site, Utc::now())) {
+            println!("    now at {}", horizontal);
+        }
+    }
+    println!();
+
+    if let Some(Command::Events { command: EventsCommand::Fetch }) = &args.command {
+        // Stale events were listed, wait for the new ones
+        if let Some(revalidation) = revalidation {
+            match revalidation.wait() {
+                Ok(evs) => {
+                    println!("Renewed {} events:", evs.len());
+                    for ev in evs.iter() {
+                        println!("{}", ev);
+                    }
+                }
+                Err(e) => {
+                    println!("Could not renew events. Error {:?}.", e);
+                    std::process::exit(1);
+                }
+            }
+        }
         return;
     }
 
+    if let Some(Command::Events { command: EventsCommand::Show { id, width } }) = &args.command {
+        let shown: Vec<&datafetch::GWEvent> =
+            gw_events.iter().filter(|ev| id.as_ref().is_none_or(|id| *id == ev.id)).collect();
+        if shown.is_empty() {
+            println!("No event {}.", id.as_deref().unwrap_or_default());
+            std::process::exit(1);
+        }
+        let mut maps = Vec::new();
+        for ev in shown {
+            match skymap::Skymap::read(&datafetch::skymap_path(&cache_dir, &ev.id)) {
+                Ok(skymap) => maps.push((ev.id.clone(), skymap)),
+                Err(e) => println!("{} {}: {}", "No skymap for".yellow(), ev.id, e),
+            }
+        }
+        if !maps.is_empty() {
+            skyview::show(&maps, *width);
+        }
+        return;
+    }
+
+    let export_events = |events: &[datafetch::GWEvent]| {
+        if let Some(dir) = &args.export_dir {
+            if let Err(e) = export::export_events(dir, events, &cache_dir) {
+                println!("{} {:?}: {}", "Warning: could not export images to".yellow(), dir, e);
+            }
+        }
+    };
+    let export_now_playing = |event: &datafetch::GWEvent| {
+        if let Some(dir) = &args.export_dir {
+            if let Err(e) = export::export_event(dir, export::NOW_PLAYING, event, &cache_dir) {
+                println!("{} {:?}: {}", "Warning: could not export images to".yellow(), dir, e);
+            }
+        }
+    };
+    export_events(&gw_events);
+
     /*
        M1_130 -> 140Hz, 4,98s
        M2_140 -> 130Hz, 9,96s
@@ -281,8 +618,8 @@ fn main() {
         if #[cfg(feature = "generate_tones")] {
             // Amplitudes adjusted to roughly match the sound samples
 
-            let source_m1 = sine_beat::SineBeat::new(140.0, 4.98).amplify(0.07);
-            let source_m2 = sine_beat::SineBeat::new(130.0, 9.96).amplify(0.08);
+            let source_m1 = sine_beat::SineBeat::new(timbre::M1.freq, timbre::M1.beat_length).amplify(timbre::M1.amplitude);
+            let source_m2 = sine_beat::SineBeat::new(timbre::M2.freq, timbre::M2.beat_length).amplify(timbre::M2.amplitude);
             let source_m3 = sine_beat::SineBeat::new(150.0, 10.).amplify(0.06);
 
             let source_m35 = sine_beat::SineBeat::new(35.0, 2.55).amplify(0.28);
@@ -292,10 +629,20 @@ fn main() {
             let tria_44_22 = triangle_wave::TriangleWave::new(44.22).amplify(0.59).repeat_infinite();
             //let tria_44_23 = triangle_wave::TriangleWave::new(44.23).repeat_infinite();
             //let tria_44_25 = triangle_wave::TriangleWave::new(44.25).repeat_infinite();
-            let tria_200 = triangle_wave::TriangleWave::new(200.0).amplify(0.53)
-                .take_duration_with_fade(Duration::from_secs(10), Duration::from_millis(500));
-            let tria_201 = triangle_wave::TriangleWave::new(201.0).amplify(0.26)
-                .take_duration_with_fade(Duration::from_secs(10), Duration::from_millis(500));
+            // One per distance::Variant
+            let tria_200 = distance::Variant::ALL.map(|variant| {
+                triangle_wave::TriangleWave::new(200.0).amplify(0.53).take_duration_with_fade(
+                    Duration::from_secs(variant.secs()), Duration::from_millis(500))
+            });
+            let tria_201 = distance::Variant::ALL.map(|variant| {
+                triangle_wave::TriangleWave::new(201.0).amplify(0.26).take_duration_with_fade(
+                    Duration::from_secs(variant.secs()), Duration::from_millis(500))
+            });
+            let detector_tones: Vec<_> = [(200.0, 0.53), (201.0, 0.26), (202.0, 0.2), (203.0, 0.53)]
+                .iter()
+                .map(|(freq, amplitude)| triangle_wave::TriangleWave::new(*freq).amplify(*amplitude)
+                    .take_duration_with_fade(Duration::from_secs(2), Duration::from_millis(200)))
+                .collect();
         } else {
             let source_m1 = source("sounds/M-1ab_130.mp3").buffered().repeat_infinite();
             let source_m2 = source("sounds/M-2ab_140.mp3").buffered().repeat_infinite();
@@ -308,14 +655,38 @@ fn main() {
             let tria_44_22 = source("sounds/Triangle_44,22-ca70-loop.mp3").buffered().repeat_infinite();
             //let tria_44_23 = source("sounds/Triangle_44,23-100-loop.mp3").buffered().repeat_infinite();
             //let tria_44_25 = source("sounds/Triangle_44,25-ca85-loop.mp3").buffered().repeat_infinite();
-            let tria_200 = source("sounds/Triangle_200-ca70 10sec oh.mp3").buffered();
-            let tria_201 = source("sounds/Triangle_201_ca30 10sec oh.mp3").buffered();
+            // One per distance::Variant
+            let tria_200 = [
+                "sounds/Triangle_200-ca70 10sec oh.mp3",
+                "sounds/Triangle_200-ca70 sec ohn.mp3",
+                "sounds/Triangle_200-ca70 2 sec oh.mp3",
+            ]
+            .map(|path| source(path).buffered());
+            let tria_201 = [
+                "sounds/Triangle_201_ca30 10sec oh.mp3",
+                "sounds/Triangle_201_ca30 5sec ohn.mp3",
+                "sounds/Triangle_201_ca30 2 sec oh.mp3",
+            ]
+            .map(|path| source(path).buffered());
+            let detector_tones: Vec<_> = [
+                "sounds/Triangle_200-ca70 2 sec oh.mp3",
+                "sounds/Triangle_201_ca30 2 sec oh.mp3",
+                "sounds/Triangle_202_ca20 2 sec oh.mp3",
+                "sounds/Triangle_203_ca70 2 sec oh.mp3",
+            ]
+            .iter()
+            .map(|path| source(path).buffered())
+            .collect();
         }
     }
 
-    let (controller, mixer) = dynamic_mixer::mixer::<f32>(2, 44_100);
-    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-    let sink = Sink::try_new(&stream_handle).unwrap();
+    let (controller, mixer) = dynamic_mixer::mixer::<f32>(layout.channels(), 44_100);
+    // Recording needs no audio device
+    let output = match args.output_wav {
+        Some(_) => None,
+        None => Some(spatial::open_output(layout.channels()).unwrap()),
+    };
+    let sink = output.as_ref().map(|(_, stream_handle)| Sink::try_new(stream_handle).unwrap());
 
     let m = MultiProgress::new();
     let pb_m1 = m.add(ProgressBar::hidden());
@@ -331,7 +702,7 @@ fn main() {
     // let (pb_m75_send, pb_m75_recv) = mpsc::channel();
 
     {
-        let progress_bars = vec![&pb_m1, &pb_m2, &pb_m3, &pb_m35, &pb_m75];
+        let progress_bars = [&pb_m1, &pb_m2, &pb_m3, &pb_m35, &pb_m75];
 
         for pb in progress_bars.iter() {
             pb.disable_steady_tick();
@@ -364,15 +735,33 @@ fn main() {
     let (tx_m200, rx_m200) = queue(true);
     let (tx_m201, rx_m201) = queue(true);
 
-    controller.add(rx_m1.convert_samples());
-    controller.add(rx_m2.convert_samples());
-    controller.add(rx_m3.convert_samples());
-    controller.add(rx_m35.convert_samples());
-    controller.add(rx_m75.convert_samples());
-    controller.add(rx_m44_00.convert_samples());
-    controller.add(rx_m44_22.convert_samples());
-    controller.add(rx_m200.convert_samples());
-    controller.add(rx_m201.convert_samples());
+    // The voices of the event sound from its direction, the drones around
+    // them from everywhere
+    let event_panner = spatial::Panner::new(&layout);
+    let bed_panner = spatial::Panner::new(&layout);
+
+    controller.add(event_panner.spatialize(rx_m1.convert_samples()));
+    controller.add(event_panner.spatialize(rx_m2.convert_samples()));
+    controller.add(event_panner.spatialize(rx_m3.convert_samples()));
+    controller.add(bed_panner.spatialize(rx_m35.convert_samples()));
+    controller.add(bed_panner.spatialize(rx_m75.convert_samples()));
+    controller.add(bed_panner.spatialize(rx_m44_00.convert_samples()));
+    controller.add(bed_panner.spatialize(rx_m44_22.convert_samples()));
+    controller.add(event_panner.spatialize(rx_m200.convert_samples()));
+    controller.add(event_panner.spatialize(rx_m201.convert_samples()));
+
+    // One voice per detector, sounding from where the detector is
+    let detector_voices: Vec<_> = detectors::DETECTORS
+        .iter()
+        .zip(detector_tones)
+        .map(|(detector, tone)| {
+            let (tx, rx) = queue(true);
+            let panner = spatial::Panner::new(&layout);
+            panner.set_direction(spatial::detector_direction(detector, site.as_ref()));
+            controller.add(panner.spatialize(rx.convert_samples()));
+            (detector.name, tone, tx)
+        })
+        .collect();
 
     struct StartEnd {
         from: DateTime<Local>,
@@ -448,14 +837,24 @@ fn main() {
         play_once(log, source, queue, duration_secs, fade_millis, volume, now_playing)
     }
 
+    // The masses crossfade into triangles and noise of the same pitch, beat
+    // and amplitude as the generated tones, by the classification
     let play_m1 = {
         let np = now_playing.clone();
-        move |secs: u64| play_repeat("M1", &source_m1, &tx_m1, secs, 100, args.vol_m1, &np)
+        let source_m1 = source_m1.convert_samples::<f32>();
+        move |secs: u64, classes: &timbre::Classes| {
+            let mixed = classes.apply(source_m1.clone(), &timbre::M1);
+            play_repeat("M1", &mixed, &tx_m1, secs, 100, args.vol_m1, &np)
+        }
     };
 
     let play_m2 = {
         let np = now_playing.clone();
-        move |secs: u64| play_repeat("M2", &source_m2, &tx_m2, secs, 100, args.vol_m2, &np)
+        let source_m2 = source_m2.convert_samples::<f32>();
+        move |secs: u64, classes: &timbre::Classes| {
+            let mixed = classes.apply(source_m2.clone(), &timbre::M2);
+            play_repeat("M2", &mixed, &tx_m2, secs, 100, args.vol_m2, &np)
+        }
     };
 
     let _play_m3 = {
@@ -477,9 +876,11 @@ fn main() {
     let play_m44_00 = {
         let np = now_playing.clone();
         let fade_in = Duration::from_secs(30);
-        let with_fade = tria_44_00.fade_in(fade_in);
-        move |secs: u64| {
-            play_once("M44.00", &with_fade, &tx_m44_00, secs, 30000, args.vol_m44_00, &np)
+        let tria_44_00 = tria_44_00.convert_samples::<f32>();
+        move |secs: u64, blur: &blur::Blur| {
+            let with_fade = blur.apply(tria_44_00.clone()).fade_in(fade_in);
+            let volume = args.vol_m44_00 * blur.level;
+            play_once("M44.00", &with_fade, &tx_m44_00, secs, 30000, volume, &np)
         }
     };
 
@@ -487,27 +888,77 @@ fn main() {
     let play_m44_22 = {
         let np = now_playing.clone();
         let fade_in = Duration::from_secs(30);
-        let with_fade = tria_44_22.fade_in(fade_in);
-        move |secs: u64| {
-            play_once("M44.22", &with_fade, &tx_m44_22, secs, 25000, args.vol_m44_22, &np)
+        let tria_44_22 = tria_44_22.convert_samples::<f32>();
+        move |secs: u64, blur: &blur::Blur| {
+            let with_fade = blur.apply(tria_44_22.clone()).fade_in(fade_in);
+            let volume = args.vol_m44_22 * blur.level;
+            play_once("M44.22", &with_fade, &tx_m44_22, secs, 25000, volume, &np)
         }
     };
 
     let play_m200 = {
         let np = now_playing.clone();
-        move |secs: u64| play_once("M200.00", &tria_200, &tx_m200, secs, 500, args.vol_m200, &np)
+        move |variant: distance::Variant, level: f32| {
+            let tria_200 = &tria_200[variant as usize];
+            let volume = args.vol_m200 * level;
+            play_once("M200.00", tria_200, &tx_m200, variant.secs(), 500, volume, &np)
+        }
     };
 
     let play_m201 = {
         let np = now_playing.clone();
-        move |secs: u64| play_once("M201.00", &tria_201, &tx_m201, secs, 500, args.vol_m201, &np)
+        move |variant: distance::Variant, level: f32| {
+            let tria_201 = &tria_201[variant as usize];
+            let volume = args.vol_m201 * level;
+            play_once("M201.00", tria_201, &tx_m201, variant.secs(), 500, volume, &np)
+        }
     };
 
-    if args.log_sample_aplitudes {
-        let logged = crate::log_source::log_source(mixer, "mixer".to_string());
-        sink.append(logged);
+    let mixer: Box<dyn Source<Item = f32> + Send> = if args.log_sample_aplitudes {
+        Box::new(crate::log_source::log_source(mixer, "mixer".to_string()))
     } else {
+        Box::new(mixer)
+    };
+    // The wavefront of an event sweeping across the detectors that saw it
+    let play_wavefront = {
+        let np = now_playing.clone();
+        let m = m.clone();
+        move |event: &datafetch::GWEvent, scale: f64| {
+            let Some(offsets) = detectors::arrival_offsets(event) else {
+                return;
+            };
+            let delays: Vec<String> = offsets
+                .iter()
+                .map(|(detector, offset)| format!("{} +{:.1} ms", detector.name, offset * 1e3))
+                .collect();
+            m.println(format!("Wavefront of {}: {}", event.id.green(), delays.join(", "))).unwrap();
+
+            for (detector, offset) in offsets {
+                let Some((name, tone, tx)) =
+                    detector_voices.iter().find(|(name, _, _)| *name == detector.name)
+                else {
+                    continue;
+                };
+                let (name, tone, tx, np) = (name.to_string(), tone.clone(), tx.clone(), np.clone());
+                let Ok(delay) = Duration::try_from_secs_f64(offset * scale) else {
+                    continue;
+                };
+                let volume = args.vol_detectors;
+                thread::spawn(move || {
+                    thread::sleep(delay);
+                    play_once(&name, &tone, &tx, 2, 200, volume, &np);
+                });
+            }
+        }
+    };
+
+    if let Some(sink) = &sink {
         sink.append(mixer);
+    } else if let Some(path) = &args.output_wav {
+        if let Err(e) = recorder::record(mixer, path) {
+            println!("Could not record to {:?}. Error {:?}.", path, e);
+            return;
+        }
     }
     //sink.set_speed(1);
     //sink.set_volume(0.3);
@@ -523,7 +974,152 @@ fn main() {
         });
     }
 
+    if let Some(dir) = args.export_dir.clone() {
+        let now_playing = now_playing.clone();
+
+        // Often enough to catch the two second cues
+        thread::spawn(move || {
+            let mut timeline = export::Timeline::default();
+            loop {
+                let now = Local::now();
+                let playing = now_playing.iter().map(|e| (e.key().clone(), e.from, e.until));
+                timeline.update(playing, now);
+                if let Err(e) = timeline.export(&dir, now) {
+                    println!("{} {:?}: {}", "Warning: could not export images to".yellow(), dir, e);
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+        });
+    }
+
+    // New events once a background renewal is done, retrying failed ones
+    let poll_renewal = |revalidation: &mut Option<cache::Revalidation>| match revalidation
+        .as_ref()
+        .and_then(|r| r.poll())?
+    {
+        Ok(evs) => {
+            m.println(format!("Switching to {} renewed events.", evs.len())).unwrap();
+            *revalidation = None;
+            (!evs.is_empty()).then(|| {
+                export_events(&evs);
+                evs
+            })
+        }
+        Err(e) => {
+            let warning = "Warning: could not renew events".yellow();
+            let retry = "Playing the stale ones and trying again later";
+            m.println(format!("{}: {}. {}.", warning, e, retry)).unwrap();
+            *revalidation = revalidation.take().map(|r| r.retry(renew.clone()));
+            None
+        }
+    };
+
+    if let Some(speedup) = args.time_lapse {
+        loop {
+            let mut renewed = None;
+            {
+                let arrivals = timelapse::schedule(&gw_events, speedup);
+                if let Some((last, _)) = arrivals.last() {
+                    let seconds = last.as_secs();
+                    println!("Time lapse of {} events over {} seconds.", arrivals.len(), seconds);
+                }
+
+                let mut alternate = false;
+                timelapse::play(&arrivals, |event| {
+                    m.println(format!("Arrival of {} ({})", event.id.green(), event.time)).unwrap();
+                    event_panner.set_direction(spatial::event_direction(event, site.as_ref()));
+                    export_now_playing(event);
+                    if let Some(scale) = args.detector_delays {
+                        play_wavefront(event, scale);
+                    } else if alternate {
+                        // Alternating the cue keeps close arrivals apart
+                        play_m201(distance::Variant::Short, 1.0);
+                    } else {
+                        play_m200(distance::Variant::Short, 1.0);
+                    }
+                    alternate = !alternate;
+
+                    // Renewed events start over from their first arrival
+                    renewed = poll_renewal(&mut revalidation);
+                    match renewed {
+                        Some(_) => ControlFlow::Break(()),
+                        None => ControlFlow::Continue(()),
+                    }
+                });
+            }
+
+            match renewed.or_else(|| poll_renewal(&mut revalidation)) {
+                Some(evs) => gw_events = evs,
+                // Pause before starting over
+                None => thread::sleep(Duration::from_secs(10)),
+            }
+        }
+    }
+
+    // Every cycle of the composition is dedicated to one event
+    let mut next_event = 0;
+
     loop {
+        // Renewed events take over from the next cycle on
+        if let Some(evs) = poll_renewal(&mut revalidation) {
+            gw_events = evs;
+            next_event = 0;
+        }
+
+        // Localization voices sound as sharp as the event is localized
+        let mut event_blur = blur::Blur::default();
+        let mut event_band = distance::band(0.0, &args.distance_bands, &args.distance_repeats);
+        let mut event_classes = timbre::Classes::default();
+        if let Some(event) = gw_events.get(next_event % gw_events.len().max(1)) {
+            let stale = match revalidation.as_ref().map(|r| r.age()) {
+                Some(Some(age)) => format!(" (stale data, {} old)", HumanDuration(age)),
+                Some(None) => " (stale data)".to_string(),
+                None => String::new(),
+            };
+            let position = match site.and_then(|site| event.horizontal(&site, Utc::now())) {
+                Some(horizontal) => format!(" at {}", horizontal),
+                None => String::new(),
+            };
+            m.println(format!("Now playing {}{}{}", event.id.green(), position, stale.yellow()))
+                .unwrap();
+            event_panner.set_direction(spatial::event_direction(event, site.as_ref()));
+            export_now_playing(event);
+            if let Some(scale) = args.detector_delays {
+                play_wavefront(event, scale);
+            }
+            event_blur = blur::Blur::for_area(event.location_area_deg2);
+            if let Some(area) = event.location_area_deg2 {
+                m.println(format!(
+                    "    {:.0} deg² localization: detune ±{:.1} cents, chorus {:.1} ms, level {:.2}",
+                    area, event_blur.detune_cents, event_blur.chorus_ms, event_blur.level
+                ))
+                .unwrap();
+            }
+            event_band =
+                distance::band(event.distance_mpc, &args.distance_bands, &args.distance_repeats);
+            m.println(format!(
+                "    {} bursts: {} cues of {} s, level {:.2}",
+                if event.distance_mpc > 0.0 {
+                    format!("{:.0} Mpc away,", event.distance_mpc)
+                } else {
+                    "Unknown distance,".to_string()
+                },
+                event_band.repeats,
+                event_band.variant.secs(),
+                event_band.level
+            ))
+            .unwrap();
+            event_classes = timbre::Classes::of(event);
+            m.println(format!(
+                "    Masses: {:.0}% sine beats (BBH), {:.0}% triangles (BNS), {:.0}% noise (terrestrial)",
+                event_classes.bbh * 100.0,
+                event_classes.bns * 100.0,
+                event_classes.terrestrial * 100.0
+            ))
+            .unwrap();
+            next_event += 1;
+        }
+
         let mut rng = rand::thread_rng();
         // Looping for around ten minutes:
         let mut remainder: u32 = 60 * 10;
@@ -538,7 +1134,23 @@ fn main() {
             } else {
                 thread::sleep(Duration::from_secs(secs.into()));
             }
-            remainder = remainder.checked_sub(secs).unwrap_or(0);
+            remainder = remainder.saturating_sub(secs);
+        };
+
+        // Alternating cues half their length apart, each voice keeps to its queue
+        let burst = |sleep: &mut dyn FnMut(u32, bool)| {
+            let gap = event_band.variant.secs().div_ceil(2) as u32;
+            for cue in 1..=event_band.repeats {
+                if cue % 2 == 1 {
+                    play_m200(event_band.variant, event_band.level);
+                } else {
+                    play_m201(event_band.variant, event_band.level);
+                }
+                sleep(gap, true);
+                if cue.is_multiple_of(6) {
+                    sleep(4, true);
+                }
+            }
         };
 
         // Starting with only M35 for ~ 30 seconds
@@ -549,83 +1161,33 @@ fn main() {
         sleep(30, false);
 
         // // First run of Masses: M1/M2 for ~ 3:30 minutes
-        play_m1(210);
+        play_m1(210, &event_classes);
         sleep(10, false);
-        play_m2(190);
+        play_m2(190, &event_classes);
         sleep(5 * 10, false);
 
         sleep(2 * 10, false);
 
         sleep(5 * 10, false);
 
-        play_m44_00(25 * 10);
+        play_m44_00(25 * 10, &event_blur);
         sleep(10, false);
-        play_m44_22(23 * 10);
+        play_m44_22(23 * 10, &event_blur);
 
         sleep(9 * 10, false);
 
-        // Short bursts of M200, M201 for 20 seconds
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        sleep(4, true);
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        sleep(4, true);
+        // Bursts of M200, M201, as long and loud as the event is near
+        burst(&mut sleep);
 
         sleep(3 * 10, false);
 
         // Second run of Masses: M1/M2 for ~ 3:30 minutes
-        play_m1(210);
+        play_m1(210, &event_classes);
         sleep(10, false);
-        play_m2(190);
-
-        // Short bursts of M200, M201 for 20 seconds
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        sleep(4, true);
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        play_m200(2);
-        sleep(1, true);
-        play_m201(2);
-        sleep(1, true);
-        sleep(4, true);
+        play_m2(190, &event_classes);
+
+        // Bursts of M200, M201, as long and loud as the event is near
+        burst(&mut sleep);
 
         //        sleep(200, false);
 
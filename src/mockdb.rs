@@ -0,0 +1,135 @@
+mod fixtures;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use clap::Parser;
+use reqwest::Url;
+
+/// Serves a fixture directory on localhost the way GraceDB serves its REST API.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Directory laid out like the GraceDB API (see fixtures.rs)
+    fixtures: PathBuf,
+
+    #[arg(long, default_value_t = 8000)]
+    port: u16,
+
+    /// Server the links in the fixtures point to. They are rewritten to point here.
+    #[arg(long, default_value = "https://gracedb.ligo.org")]
+    origin: String,
+}
+
+// FNV-1a, stable across runs so that clients can revalidate
+fn etag(body: &[u8]) -> String {
+    let hash =
+        body.iter().fold(0xcbf29ce484222325u64, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3));
+    format!("\"{:016x}\"", hash)
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nETag: {}\r\nConnection: close\r\n\r\n",
+        body.len(),
+        etag(body)
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn handle(mut stream: TcpStream, root: &Path, origin: &str, own: &str) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut if_none_match = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("if-none-match") {
+                if_none_match = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or("/");
+
+    if method != "GET" {
+        println!("{method} {target} -> 405");
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+
+    let Ok(url) = Url::parse(&format!("{own}{target}")) else {
+        println!("{method} {target} -> 400");
+        return respond(&mut stream, "400 Bad Request", "text/plain", b"");
+    };
+
+    let path = fixtures::fixture_path(root, &url);
+    match fs::read(&path) {
+        Ok(body) => {
+            let (content_type, body) = if path.extension().is_some_and(|ext| ext == "json") {
+                let body = String::from_utf8_lossy(&body).replace(origin, own);
+                ("application/json", body.into_bytes())
+            } else {
+                ("application/octet-stream", body)
+            };
+
+            if if_none_match.is_some_and(|tag| tag == etag(&body)) {
+                println!("{method} {target} -> 304 {:?}", path);
+                write!(
+                    stream,
+                    "HTTP/1.1 304 Not Modified\r\nETag: {}\r\nConnection: close\r\n\r\n",
+                    etag(&body)
+                )?;
+                return stream.flush();
+            }
+
+            println!("{method} {target} -> 200 {:?}", path);
+            respond(&mut stream, "200 OK", content_type, &body)
+        }
+        Err(_) => {
+            println!("{method} {target} -> 404 {:?}", path);
+            respond(&mut stream, "404 Not Found", "application/json", br#"{"detail":"Not found."}"#)
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let listener = TcpListener::bind(("127.0.0.1", args.port)).unwrap();
+    let own = format!("http://{}", listener.local_addr().unwrap());
+    println!("Serving {:?} on {own}/apiweb/", args.fixtures);
+
+    let root = Arc::new(args.fixtures);
+    let origin = Arc::new(args.origin);
+    let own = Arc::new(own);
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let (root, origin, own) = (root.clone(), origin.clone(), own.clone());
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &root, &origin, &own) {
+                println!("Error answering request: {:?}", e);
+            }
+        });
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use rodio::source::Source;
+
+// Corner of the low-pass taking the hiss off the white noise
+const CUTOFF_HZ: f32 = 1000.0;
+
+/// An infinite source of white noise through a one pole low-pass, about as
+/// loud as a sine of amplitude 0.7.
+///
+/// Always has a rate of 48kHz and one channel.
+#[derive(Clone, Debug)]
+pub struct Noise {
+    state: u64,
+    smoothing: f32,
+    gain: f32,
+    value: f32,
+}
+
+impl Noise {
+    #[inline]
+    pub fn new(seed: u64) -> Noise {
+        let smoothing = 1.0 - (-2.0 * std::f32::consts::PI * CUTOFF_HZ / 48000.0).exp();
+        // Uniform noise has a power of 1/3, the low-pass keeps a/(2 - a) of it
+        let rms = (smoothing / (2.0 - smoothing) / 3.0).sqrt();
+        Noise { state: seed | 1, smoothing, gain: 0.5 / rms, value: 0.0 }
+    }
+}
+
+impl Iterator for Noise {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let bits = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40;
+        let white = bits as f32 / (1u64 << 23) as f32 - 1.0;
+
+        self.value += self.smoothing * (white - self.value);
+        Some(self.value * self.gain)
+    }
+}
+
+impl Source for Noise {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        48000
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,114 @@
+// Where an event is on the sky as seen from the installation. Precession and
+// nutation are left out; over a few decades they move a source by well under
+// a degree, far less than any skymap is wide.
+
+use chrono::{DateTime, Utc};
+
+use crate::skymap::SkyPosition;
+
+// Julian date of the unix epoch and of J2000.0
+const JD_UNIX_EPOCH: f64 = 2_440_587.5;
+const JD_J2000: f64 = 2_451_545.0;
+
+/// Geographic position of the installation in degrees, longitude east.
+#[derive(Debug, Clone, Copy)]
+pub struct Site {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Position relative to the local horizon in degrees. Azimuth counts from
+/// north through east.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HorizontalPosition {
+    pub alt: f64,
+    pub az: f64,
+}
+
+impl HorizontalPosition {
+    pub fn is_above_horizon(&self) -> bool {
+        self.alt > 0.0
+    }
+}
+
+impl std::fmt::Display for HorizontalPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let side = if self.is_above_horizon() { "above" } else { "below" };
+        write!(f, "alt={:+.1}° az={:.1}° ({} horizon)", self.alt, self.az, side)
+    }
+}
+
+fn julian_date(time: DateTime<Utc>) -> f64 {
+    let seconds = time.timestamp() as f64 + time.timestamp_subsec_nanos() as f64 * 1e-9;
+    JD_UNIX_EPOCH + seconds / 86_400.0
+}
+
+/// Greenwich mean sidereal time in degrees (IAU 1982).
+pub fn gmst(time: DateTime<Utc>) -> f64 {
+    let d = julian_date(time) - JD_J2000;
+    let t = d / 36_525.0;
+    let gmst =
+        280.460_618_37 + 360.985_647_366_29 * d + 0.000_387_933 * t * t - t * t * t / 38_710_000.0;
+    gmst.rem_euclid(360.0)
+}
+
+impl Site {
+    /// Local mean sidereal time in degrees.
+    pub fn local_sidereal_time(&self, time: DateTime<Utc>) -> f64 {
+        (gmst(time) + self.lon).rem_euclid(360.0)
+    }
+
+    pub fn horizontal(&self, position: SkyPosition, time: DateTime<Utc>) -> HorizontalPosition {
+        let hour_angle = (self.local_sidereal_time(time) - position.ra).to_radians();
+        let (dec, lat) = (position.dec.to_radians(), self.lat.to_radians());
+
+        let sin_alt = dec.sin() * lat.sin() + dec.cos() * lat.cos() * hour_angle.cos();
+        let alt = sin_alt.clamp(-1.0, 1.0).asin();
+        let az = (-hour_angle.sin() * dec.cos())
+            .atan2(dec.sin() * lat.cos() - dec.cos() * lat.sin() * hour_angle.cos());
+
+        HorizontalPosition { alt: alt.to_degrees(), az: az.to_degrees().rem_euclid(360.0) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn gmst_at_j2000() {
+        let j2000 = Utc.with_ymd_and_hms(2000, 1, 1, 12, 0, 0).unwrap();
+        assert!((julian_date(j2000) - JD_J2000).abs() < 1e-9);
+        assert!((gmst(j2000) - 280.4606).abs() < 1e-4, "{}", gmst(j2000));
+        // Meeus, Astronomical Algorithms, example 12.a: 13h10m46.3668s
+        let time = Utc.with_ymd_and_hms(1987, 4, 10, 0, 0, 0).unwrap();
+        assert!((gmst(time) - 197.693_195).abs() < 1e-4, "{}", gmst(time));
+    }
+
+    #[test]
+    fn venus_from_washington() {
+        // Meeus, example 13.b: Venus seen from the US Naval Observatory
+        let site = Site { lat: 38.921_389, lon: -77.065_556 };
+        let venus = SkyPosition { ra: 347.319_3, dec: -6.719_9 };
+        let time = Utc.with_ymd_and_hms(1987, 4, 10, 19, 21, 0).unwrap();
+        let horizontal = site.horizontal(venus, time);
+        // Meeus counts the azimuth from the south and uses the apparent
+        // sidereal time, 0.004° off the mean one that day
+        assert!((horizontal.alt - 15.1249).abs() < 0.01, "{}", horizontal);
+        assert!((horizontal.az - (68.0337 + 180.0)).abs() < 0.01, "{}", horizontal);
+        assert!(horizontal.is_above_horizon());
+    }
+
+    #[test]
+    fn polaris_stands_at_the_latitude() {
+        let polaris = SkyPosition { ra: 37.95, dec: 89.264 };
+        let site = Site { lat: 52.38, lon: 9.72 };
+        for hour in 0..24 {
+            let time = Utc.with_ymd_and_hms(2024, 3, 20, hour, 0, 0).unwrap();
+            let horizontal = site.horizontal(polaris, time);
+            assert!((horizontal.alt - site.lat).abs() < 0.75, "{}", horizontal);
+            assert!(horizontal.az < 1.5 || horizontal.az > 358.5, "{}", horizontal);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+// Cosmology and statistics needed to turn the published numbers into the
+// quantities we play with.
+
+pub const SECONDS_PER_YEAR: f64 = 365.25 * 24.0 * 3600.0;
+
+/// Half width of the central 90% of a normal distribution in standard deviations
+pub const NORMAL_90: f64 = 1.6448536269514722;
+
+// Planck 2015 flat ΛCDM, as used by the LIGO/Virgo/KAGRA analyses
+const H0: f64 = 67.74; // km/s/Mpc
+const OMEGA_M: f64 = 0.3075;
+pub const SPEED_OF_LIGHT: f64 = 299_792.458; // km/s
+
+const HUBBLE_DISTANCE_MPC: f64 = SPEED_OF_LIGHT / H0;
+
+fn inverse_hubble_parameter(z: f64) -> f64 {
+    let zp1 = 1.0 + z;
+    1.0 / (OMEGA_M * zp1 * zp1 * zp1 + (1.0 - OMEGA_M)).sqrt()
+}
+
+/// Luminosity distance in Mpc of a source at redshift `z`.
+pub fn luminosity_distance_mpc(z: f64) -> f64 {
+    // Simpson's rule, plenty for the precision of any measured distance
+    const STEPS: usize = 256;
+    let h = z / STEPS as f64;
+    let sum: f64 = (0..=STEPS)
+        .map(|i| {
+            let weight = match i {
+                0 | STEPS => 1.0,
+                i if i % 2 == 1 => 4.0,
+                _ => 2.0,
+            };
+            weight * inverse_hubble_parameter(i as f64 * h)
+        })
+        .sum();
+    let comoving = HUBBLE_DISTANCE_MPC * sum * h / 3.0;
+    (1.0 + z) * comoving
+}
+
+/// Redshift of a source at the luminosity distance `distance_mpc`.
+pub fn redshift(distance_mpc: f64) -> f64 {
+    if distance_mpc <= 0.0 {
+        return 0.0;
+    }
+    // The distance grows monotonically with z, so bisection is safe
+    let (mut low, mut high) = (0.0, 1.0);
+    while luminosity_distance_mpc(high) < distance_mpc && high < 1e3 {
+        high *= 2.0;
+    }
+    for _ in 0..60 {
+        let mid = (low + high) / 2.0;
+        if luminosity_distance_mpc(mid) < distance_mpc {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Value below which a standard normal variable falls with probability `p`
+/// (Acklam's approximation, relative error below 1.2e-9).
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] =
+        [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996, 3.754408661907416];
+    const P_LOW: f64 = 0.02425;
+
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+
+    if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p <= 1.0 - P_LOW {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn planck15_redshifts() {
+        // Astropy's Planck15 gives 475.4 Mpc at z = 0.1
+        assert!(
+            (luminosity_distance_mpc(0.1) - 475.4).abs() < 1.0,
+            "{}",
+            luminosity_distance_mpc(0.1)
+        );
+        assert!((redshift(475.4) - 0.1).abs() < 1e-3, "{}", redshift(475.4));
+        // GW150914 at 440 Mpc, z = 0.09
+        assert!((redshift(440.0) - 0.093).abs() < 2e-3, "{}", redshift(440.0));
+        assert_eq!(redshift(0.0), 0.0);
+        assert_eq!(redshift(-5.0), 0.0);
+        for distance in [10.0, 1_000.0, 50_000.0] {
+            let back = luminosity_distance_mpc(redshift(distance));
+            assert!((back - distance).abs() < distance * 1e-9, "{} {}", distance, back);
+        }
+    }
+
+    #[test]
+    fn normal_quantiles() {
+        assert!((normal_quantile(0.95) - 1.644_853_6).abs() < 1e-6);
+        assert!((normal_quantile(0.95) - NORMAL_90).abs() < 1e-8);
+        assert!((normal_quantile(0.05) + NORMAL_90).abs() < 1e-8);
+        assert!(normal_quantile(0.5).abs() < 1e-12);
+        assert!((normal_quantile(0.975) - 1.959_964).abs() < 1e-6);
+        // The tails
+        assert!((normal_quantile(0.001) + 3.090_232).abs() < 1e-5);
+        assert!((normal_quantile(0.999) - 3.090_232).abs() < 1e-5);
+        assert_eq!(normal_quantile(0.0), f64::NEG_INFINITY);
+        assert_eq!(normal_quantile(1.0), f64::INFINITY);
+    }
+}
@@ -0,0 +1,104 @@
+// Writes the mix to a WAV file instead of an audio device, e.g. B-format for
+// the Ambisonics decoder of a dome. The composition runs in real time, so the
+// file is filled at the pace the device would have played it.
+
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rodio::Source;
+
+// How often we catch up with the clock
+const TICK: Duration = Duration::from_millis(100);
+
+// The header is updated this often, so that files of a killed process play
+const FLUSH_TICKS: u32 = 10;
+
+// WAV keeps sizes in 32 bits, so a recording goes on in a new file well
+// before 4 GiB: after about 23 minutes of 3rd order Ambisonics
+const MAX_DATA_BYTES: u64 = 4_000_000_000;
+
+/// Path of the `n`th file of a recording to `path`: mix.wav, mix-2.wav, …
+fn part_path(path: &Path, n: u32) -> PathBuf {
+    if n == 1 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+// Without the recording there is nothing left to do
+fn fail(path: &Path, e: hound::Error) -> ! {
+    println!("Could not write the recording to {:?}. Error {:?}.", path, e);
+    std::process::exit(1);
+}
+
+/// Starts writing `source` to 32 bit float WAV files at `path`, continued in
+/// mix-2.wav, mix-3.wav, … for a `path` of mix.wav.
+pub fn record<S>(mut source: S, path: &Path) -> Result<(), hound::Error>
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    let channels = source.channels();
+    let spec = WavSpec {
+        channels,
+        sample_rate: source.sample_rate(),
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(path, spec)?;
+    println!("Recording {} channels to {:?}.", channels, path);
+    let frames_per_file = MAX_DATA_BYTES / (4 * channels as u64);
+
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        let start = Instant::now();
+        let mut frames_written: u64 = 0;
+        let mut part = 1;
+        let mut current = path.clone();
+        for tick in 1.. {
+            thread::sleep(TICK);
+            let frames_due = (start.elapsed().as_secs_f64() * spec.sample_rate as f64) as u64;
+            for frame in frames_written..frames_due {
+                if frame > 0 && frame % frames_per_file == 0 {
+                    part += 1;
+                    let next_path = part_path(&path, part);
+                    let next =
+                        WavWriter::create(&next_path, spec).unwrap_or_else(|e| fail(&next_path, e));
+                    std::mem::replace(&mut writer, next)
+                        .finalize()
+                        .unwrap_or_else(|e| fail(&current, e));
+                    println!("Recording goes on in {:?}.", next_path);
+                    current = next_path;
+                }
+                for _ in 0..channels {
+                    let sample = source.next().unwrap_or(0.0);
+                    writer.write_sample(sample).unwrap_or_else(|e| fail(&current, e));
+                }
+            }
+            frames_written = frames_due;
+
+            if tick % FLUSH_TICKS == 0 {
+                writer.flush().unwrap_or_else(|e| fail(&current, e));
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn part_paths() {
+        assert_eq!(part_path(Path::new("out/mix.wav"), 1), Path::new("out/mix.wav"));
+        assert_eq!(part_path(Path::new("out/mix.wav"), 2), Path::new("out/mix-2.wav"));
+        assert_eq!(part_path(Path::new("mix"), 3), Path::new("mix-3"));
+    }
+}
@@ -0,0 +1,337 @@
+// Multiorder skymaps as published with every alert (bayestar.multiorder.fits).
+// The probability density is stored per pixel of varying size, indexed with
+// NUNIQ, in a binary table. See https://emfollow.docs.ligo.org/userguide/tutorial/multiorder_skymaps.html
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::datafetch::FetchError;
+use crate::fits::{self, Hdu};
+use crate::healpix;
+
+const SQUARE_DEGREES_PER_STERADIAN: f64 =
+    (180.0 / std::f64::consts::PI) * (180.0 / std::f64::consts::PI);
+
+/// Position on the sky in equatorial coordinates, in degrees.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct SkyPosition {
+    pub ra: f64,
+    pub dec: f64,
+}
+
+impl SkyPosition {
+    pub fn from_ang(theta: f64, phi: f64) -> Self {
+        SkyPosition { ra: phi.to_degrees(), dec: 90.0 - theta.to_degrees() }
+    }
+
+    // Colatitude and longitude in radians
+    fn to_ang(self) -> (f64, f64) {
+        ((90.0 - self.dec).to_radians(), self.ra.rem_euclid(360.0).to_radians())
+    }
+}
+
+impl std::fmt::Display for SkyPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "RA={:.2}° Dec={:+.2}°", self.ra, self.dec)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pixel {
+    pub order: u8,
+    pub ipix: u64,
+    // Probability per steradian
+    pub probdensity: f64,
+}
+
+impl Pixel {
+    /// Area in steradians.
+    pub fn area(&self) -> f64 {
+        healpix::pixel_area(self.order)
+    }
+
+    pub fn probability(&self) -> f64 {
+        self.probdensity * self.area()
+    }
+
+    pub fn center(&self) -> SkyPosition {
+        let (theta, phi) = healpix::nest2ang(self.order, self.ipix);
+        SkyPosition::from_ang(theta, phi)
+    }
+}
+
+pub struct Skymap {
+    // Densest first, the order credible regions grow in
+    pixels: Vec<Pixel>,
+    // Probability of all pixels up to and including each one
+    cumulative: Vec<f64>,
+    index: HashMap<u64, usize>,
+    orders: BTreeSet<u8>,
+}
+
+fn fits_error(path: &Path, reason: impl Into<String>) -> FetchError {
+    FetchError::Fits { path: path.to_path_buf(), reason: reason.into() }
+}
+
+// Byte width of a binary table column, e.g. "D" or "1K"
+fn column_width(tform: &str) -> Option<usize> {
+    let tform = tform.trim();
+    let split = tform.find(|c: char| !c.is_ascii_digit())?;
+    let repeat = if split == 0 { 1 } else { tform[..split].parse().ok()? };
+    let size = match &tform[split..split + 1] {
+        "L" | "X" | "B" | "A" => 1,
+        "I" => 2,
+        "J" | "E" => 4,
+        "K" | "D" => 8,
+        "C" => 8,
+        "M" => 16,
+        _ => return None,
+    };
+    Some(repeat * size)
+}
+
+fn read_value(bytes: &[u8], tform: &str) -> Option<f64> {
+    let code = tform.trim().trim_start_matches(|c: char| c.is_ascii_digit());
+    Some(match code {
+        "K" => i64::from_be_bytes(bytes.get(..8)?.try_into().ok()?) as f64,
+        "J" => i32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as f64,
+        "D" => f64::from_be_bytes(bytes.get(..8)?.try_into().ok()?),
+        "E" => f32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as f64,
+        _ => return None,
+    })
+}
+
+// Column name to (byte offset, TFORM)
+fn columns(hdu: &Hdu) -> HashMap<String, (usize, String)> {
+    let mut result = HashMap::new();
+    let mut offset = 0;
+    for i in 1.. {
+        let (Some(name), Some(tform)) =
+            (hdu.value(&format!("TTYPE{i}")), hdu.value(&format!("TFORM{i}")))
+        else {
+            break;
+        };
+        let Some(width) = column_width(tform) else {
+            break;
+        };
+        result.insert(name.trim().to_uppercase(), (offset, tform.to_string()));
+        offset += width;
+    }
+    result
+}
+
+impl Skymap {
+    /// Reads the pixels of a NUNIQ ordered multiorder skymap.
+    pub fn read(path: &Path) -> Result<Skymap, FetchError> {
+        let bytes = fs::read(path).map_err(|e| fits_error(path, e.to_string()))?;
+        let hdus = fits::hdus(&bytes).map_err(|reason| fits_error(path, reason))?;
+
+        let hdu = hdus
+            .iter()
+            .find(|hdu| hdu.value("ORDERING") == Some("NUNIQ"))
+            .ok_or_else(|| fits_error(path, "no NUNIQ ordered table"))?;
+
+        let row_width = match hdu.integer("NAXIS1") {
+            Some(n) if n > 0 && hdu.integer("BITPIX") == Some(8) => n as usize,
+            _ => return Err(fits_error(path, "no row width")),
+        };
+        let columns = columns(hdu);
+        // Columns past the end of a row
+        if columns
+            .values()
+            .any(|(offset, tform)| offset + column_width(tform).unwrap_or(0) > row_width)
+        {
+            return Err(fits_error(path, "columns wider than the rows"));
+        }
+        let (Some(uniq), Some(density)) = (columns.get("UNIQ"), columns.get("PROBDENSITY")) else {
+            return Err(fits_error(path, "no UNIQ and PROBDENSITY columns"));
+        };
+
+        // The rows, without the heap after them
+        let rows = hdu.integer("NAXIS2").unwrap_or(0).max(0) as usize;
+        let table = &hdu.data[..rows.saturating_mul(row_width).min(hdu.data.len())];
+
+        let mut pixels = Vec::with_capacity(rows);
+        for row in table.chunks_exact(row_width) {
+            let value = |(offset, tform): &(usize, String)| read_value(&row[*offset..], tform);
+            let (Some(uniq), Some(probdensity)) = (value(uniq), value(density)) else {
+                return Err(fits_error(path, "unsupported column format"));
+            };
+            let Some((order, ipix)) = healpix::uniq2nest(uniq as u64) else {
+                return Err(fits_error(path, format!("invalid UNIQ {uniq}")));
+            };
+            pixels.push(Pixel { order, ipix, probdensity });
+        }
+
+        if pixels.is_empty() {
+            return Err(fits_error(path, "no pixels"));
+        }
+        Ok(Skymap::new(pixels))
+    }
+
+    fn new(mut pixels: Vec<Pixel>) -> Self {
+        pixels.sort_by(|a, b| b.probdensity.total_cmp(&a.probdensity));
+
+        // Normalized, as rounding leaves the total a little off one
+        let total: f64 = pixels.iter().map(Pixel::probability).sum();
+        let mut sum = 0.0;
+        let cumulative = pixels
+            .iter()
+            .map(|pixel| {
+                sum += pixel.probability() / total;
+                sum
+            })
+            .collect();
+
+        let index = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| (healpix::uniq(pixel.order, pixel.ipix), i))
+            .collect();
+        let orders = pixels.iter().map(|pixel| pixel.order).collect();
+
+        Skymap { pixels, cumulative, index, orders }
+    }
+
+    /// All pixels, densest first.
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    /// The smallest set of pixels containing the probability `p`.
+    pub fn credible_region(&self, p: f64) -> &[Pixel] {
+        let n = self.cumulative.partition_point(|sum| *sum < p);
+        &self.pixels[..(n + 1).min(self.pixels.len())]
+    }
+
+    /// Area of the smallest region containing the probability `p`, in deg².
+    pub fn credible_area_deg2(&self, p: f64) -> f64 {
+        let area: f64 = self.credible_region(p).iter().map(Pixel::area).sum();
+        area * SQUARE_DEGREES_PER_STERADIAN
+    }
+
+    /// Most probable direction of the source.
+    pub fn max_probability(&self) -> SkyPosition {
+        self.pixels[0].center()
+    }
+
+    fn pixel_at(&self, position: SkyPosition) -> Option<usize> {
+        let (theta, phi) = position.to_ang();
+        self.orders.iter().find_map(|&order| {
+            let ipix = healpix::ang2nest(order, theta, phi);
+            self.index.get(&healpix::uniq(order, ipix)).copied()
+        })
+    }
+
+    /// Probability per deg² at `position`.
+    pub fn probability_density_at(&self, position: SkyPosition) -> f64 {
+        self.pixel_at(position)
+            .map_or(0.0, |i| self.pixels[i].probdensity / SQUARE_DEGREES_PER_STERADIAN)
+    }
+
+    /// Smallest credible level whose region contains `position`, e.g. 0.9
+    /// for a position just inside the 90% region.
+    pub fn credible_level_at(&self, position: SkyPosition) -> f64 {
+        self.pixel_at(position).map_or(1.0, |i| self.cumulative[i])
+    }
+}
+
+/// Path of a skymap and what `gwrust skymap` should tell about it.
+#[derive(Debug, Clone)]
+pub struct SkymapQuery {
+    pub path: PathBuf,
+    pub position: Option<SkyPosition>,
+    pub levels: Vec<f64>,
+}
+
+/// Prints the summary of a skymap for the command line.
+pub fn describe(query: &SkymapQuery) -> Result<(), FetchError> {
+    let skymap = Skymap::read(&query.path)?;
+
+    let orders: Vec<String> = skymap.orders.iter().map(u8::to_string).collect();
+    println!("{:?}: {} pixels of order {}", query.path, skymap.pixels().len(), orders.join(", "));
+    println!("Most probable direction: {}", skymap.max_probability());
+    for level in &query.levels {
+        let region = skymap.credible_region(*level);
+        println!(
+            "{:>4.0}% credible region: {:.1} deg² in {} pixels",
+            level * 100.0,
+            skymap.credible_area_deg2(*level),
+            region.len()
+        );
+    }
+    if let Some(position) = query.position {
+        println!(
+            "At {}: {:.3e} per deg², inside the {:.1}% credible region",
+            position,
+            skymap.probability_density_at(position),
+            skymap.credible_level_at(position) * 100.0
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL_SKY_DEG2: f64 = 4.0 * std::f64::consts::PI * SQUARE_DEGREES_PER_STERADIAN;
+
+    fn pixel(order: u8, ipix: u64, probability: f64) -> Pixel {
+        Pixel { order, ipix, probdensity: probability / healpix::pixel_area(order) }
+    }
+
+    // Half of the probability in one of the four order 1 pixels of base
+    // pixel 0, the rest spread over the sky
+    fn skymap() -> Skymap {
+        let mut pixels =
+            vec![pixel(1, 0, 0.5), pixel(1, 1, 0.3), pixel(1, 2, 0.05), pixel(1, 3, 0.05)];
+        pixels.extend((1..12).map(|ipix| pixel(0, ipix, 0.1 / 11.0)));
+        Skymap::new(pixels)
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn credible_areas() {
+        let skymap = skymap();
+        assert_close(FULL_SKY_DEG2, 41252.96124941928);
+        assert_close(skymap.credible_area_deg2(0.4), FULL_SKY_DEG2 / 48.0);
+        assert_close(skymap.credible_area_deg2(0.7), FULL_SKY_DEG2 / 24.0);
+        // Both order 1 pixels of 5% are denser than any base pixel
+        assert_close(skymap.credible_area_deg2(0.88), FULL_SKY_DEG2 / 12.0);
+        assert_close(skymap.credible_area_deg2(0.905), FULL_SKY_DEG2 / 12.0 * 2.0);
+        assert_close(skymap.credible_area_deg2(1.0), FULL_SKY_DEG2);
+    }
+
+    #[test]
+    fn lookups() {
+        let skymap = skymap();
+        let (theta, phi) = healpix::nest2ang(1, 0);
+        assert_eq!(skymap.max_probability(), SkyPosition::from_ang(theta, phi));
+
+        let south_pole = SkyPosition { ra: 0.0, dec: -90.0 };
+        let density = 0.1 / 11.0 / healpix::pixel_area(0) / SQUARE_DEGREES_PER_STERADIAN;
+        assert_close(skymap.probability_density_at(south_pole), density);
+        let level = skymap.credible_level_at(south_pole);
+        assert!(level > 0.9 && level <= 1.0, "{}", level);
+        assert_close(skymap.credible_level_at(skymap.max_probability()), 0.5);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let dir = std::env::temp_dir().join(format!("gwrust-skymap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("broken.fits");
+        for bytes in [&b""[..], &[b' '; 2880][..], &b"SIMPLE  =                    T"[..]] {
+            fs::write(&path, bytes).unwrap();
+            assert!(matches!(Skymap::read(&path), Err(FetchError::Fits { .. })));
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,235 @@
+// Skymaps drawn in the terminal as a Mollweide projection. Braille characters
+// give every cell 2 x 4 dots for the contours; the colors are the sixteen
+// every terminal has, so the maps also show over SSH. Right ascension grows
+// to the left, as on the sky seen from inside.
+
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use colored::{Color, Colorize};
+
+use crate::skymap::{SkyPosition, Skymap};
+
+pub const CREDIBLE_LEVELS: [f64; 2] = [0.5, 0.9];
+
+// Density relative to the peak from which a cell gets the color
+const DENSITY_COLORS: [(f64, Color); 4] =
+    [(0.5, Color::BrightRed), (0.2, Color::Red), (0.05, Color::Yellow), (0.01, Color::Green)];
+const FAINT_COLOR: Color = Color::Blue;
+
+// One color per event when several share the map
+const EVENT_COLORS: [Color; 6] = [
+    Color::BrightRed,
+    Color::BrightGreen,
+    Color::BrightYellow,
+    Color::BrightCyan,
+    Color::BrightMagenta,
+    Color::BrightBlue,
+];
+
+// Bits of the braille dots by column and row within a cell
+const BRAILLE_BASE: u32 = 0x2800;
+const BRAILLE_DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+/// Position on the sky at `x` and `y` of a Mollweide ellipse scaled to -1..1
+/// both ways, `None` outside of it.
+pub fn inverse_mollweide(x: f64, y: f64) -> Option<SkyPosition> {
+    if x * x + y * y > 1.0 {
+        return None;
+    }
+    let theta = y.asin();
+    let lat = ((2.0 * theta + (2.0 * theta).sin()) / PI).clamp(-1.0, 1.0).asin();
+    let lon = if theta.cos() > 1e-9 { PI * x / theta.cos() } else { 0.0 };
+    Some(SkyPosition { ra: (180.0 - lon.to_degrees()).rem_euclid(360.0), dec: lat.to_degrees() })
+}
+
+/// Inverse of `inverse_mollweide`.
+pub fn mollweide(position: SkyPosition) -> (f64, f64) {
+    let lat = position.dec.to_radians();
+    // Solve 2θ + sin 2θ = π sin φ with Newton's method
+    let mut theta = lat;
+    for _ in 0..50 {
+        if theta.abs() >= FRAC_PI_2 - 1e-9 {
+            break;
+        }
+        let step = (2.0 * theta + (2.0 * theta).sin() - PI * lat.sin())
+            / (2.0 + 2.0 * (2.0 * theta).cos());
+        theta -= step;
+        if step.abs() < 1e-9 {
+            break;
+        }
+    }
+    let lon = (180.0 - position.ra.rem_euclid(360.0)).to_radians();
+    (lon * theta.cos() / PI, theta.sin())
+}
+
+/// 0 inside the 50% region, 1 inside the 90% region, 2 outside of both.
+pub fn bucket(level: f64) -> usize {
+    CREDIBLE_LEVELS.iter().position(|l| level <= *l).unwrap_or(CREDIBLE_LEVELS.len())
+}
+
+#[derive(Clone, Copy)]
+struct Dot {
+    // Event whose skymap is most credible here
+    map: usize,
+    bucket: usize,
+    // Density relative to the peak of that skymap
+    density: f64,
+}
+
+/// Draws `maps` together, `width` characters wide.
+pub fn render(maps: &[(String, Skymap)], width: usize) -> Vec<String> {
+    let (cols, rows) = (width.max(8), (width / 4).max(2));
+    let (dots_x, dots_y) = (cols * 2, rows * 4);
+
+    let peaks: Vec<f64> = maps
+        .iter()
+        .map(|(_, skymap)| skymap.probability_density_at(skymap.max_probability()))
+        .collect();
+    let dots: Vec<Vec<Option<Dot>>> = (0..dots_y)
+        .map(|j| {
+            (0..dots_x)
+                .map(|i| {
+                    let x = (i as f64 + 0.5) / dots_x as f64 * 2.0 - 1.0;
+                    let y = 1.0 - (j as f64 + 0.5) / dots_y as f64 * 2.0;
+                    let position = inverse_mollweide(x, y)?;
+                    let (map, level) = maps
+                        .iter()
+                        .enumerate()
+                        .map(|(k, (_, skymap))| (k, skymap.credible_level_at(position)))
+                        .min_by(|a, b| a.1.total_cmp(&b.1))
+                        .unwrap_or((0, 1.0));
+                    let density = maps.get(map).map_or(0.0, |(_, skymap)| {
+                        skymap.probability_density_at(position) / peaks[map].max(f64::MIN_POSITIVE)
+                    });
+                    Some(Dot { map, bucket: bucket(level), density })
+                })
+                .collect()
+        })
+        .collect();
+
+    let at = |i: isize, j: isize| -> Option<Dot> {
+        if i < 0 || j < 0 {
+            return None;
+        }
+        *dots.get(j as usize)?.get(i as usize)?
+    };
+    let is_set = |i: usize, j: usize| -> bool {
+        let Some(dot) = dots[j][i] else {
+            return false;
+        };
+        let neighbours =
+            [(-1, 0), (1, 0), (0, -1), (0, 1)].map(|(di, dj)| at(i as isize + di, j as isize + dj));
+        let outline = neighbours.iter().any(Option::is_none);
+        let contour = dot.bucket < CREDIBLE_LEVELS.len()
+            && neighbours.iter().flatten().any(|n| n.bucket > dot.bucket || n.map != dot.map);
+        let fill = match dot.bucket {
+            0 => (i + j).is_multiple_of(2),
+            1 => i.is_multiple_of(2) && j.is_multiple_of(4),
+            _ => false,
+        };
+        outline || contour || fill
+    };
+
+    // Cells holding the most probable direction of an event
+    let markers: Vec<(usize, usize)> = maps
+        .iter()
+        .map(|(_, skymap)| {
+            let (x, y) = mollweide(skymap.max_probability());
+            let col = ((x + 1.0) / 2.0 * cols as f64) as usize;
+            let row = ((1.0 - y) / 2.0 * rows as f64) as usize;
+            (col.min(cols - 1), row.min(rows - 1))
+        })
+        .collect();
+
+    (0..rows)
+        .map(|row| {
+            (0..cols)
+                .map(|col| {
+                    let mut bits = 0;
+                    let mut best: Option<Dot> = None;
+                    for (di, column) in BRAILLE_DOTS.iter().enumerate() {
+                        for (dj, bit) in column.iter().enumerate() {
+                            let (i, j) = (col * 2 + di, row * 4 + dj);
+                            if is_set(i, j) {
+                                bits |= bit;
+                            }
+                            if let Some(dot) = dots[j][i] {
+                                if dot.bucket < CREDIBLE_LEVELS.len()
+                                    && best.is_none_or(|b| dot.density > b.density)
+                                {
+                                    best = Some(dot);
+                                }
+                            }
+                        }
+                    }
+
+                    let color = best.map(|dot| {
+                        if maps.len() > 1 {
+                            EVENT_COLORS[dot.map % EVENT_COLORS.len()]
+                        } else {
+                            density_color(dot.density)
+                        }
+                    });
+                    if let Some(k) = markers.iter().position(|m| *m == (col, row)) {
+                        let color = if maps.len() > 1 {
+                            EVENT_COLORS[k % EVENT_COLORS.len()]
+                        } else {
+                            Color::BrightWhite
+                        };
+                        return "+".color(color).bold().to_string();
+                    }
+
+                    let c = char::from_u32(BRAILLE_BASE + bits).unwrap_or(' ');
+                    match color {
+                        Some(color) => c.to_string().color(color).to_string(),
+                        None if bits != 0 => c.to_string().dimmed().to_string(),
+                        None => " ".to_string(),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn density_color(density: f64) -> Color {
+    DENSITY_COLORS.iter().find(|(min, _)| density >= *min).map_or(FAINT_COLOR, |(_, c)| *c)
+}
+
+/// Prints `maps` together with the axis and a legend.
+pub fn show(maps: &[(String, Skymap)], width: usize) {
+    let lines = render(maps, width);
+    let cols = width.max(8);
+
+    println!("Mollweide projection, 50% and 90% credible regions, + most probable direction");
+    for line in lines {
+        println!("{}", line);
+    }
+    let left = cols.saturating_sub(3) / 2;
+    let right = cols.saturating_sub(3 + left);
+    let axis = format!("{:<left$}12h{:>right$}", "24h", "0h");
+    println!("{}", axis.dimmed());
+    println!();
+
+    if maps.len() == 1 {
+        let scale: Vec<String> = DENSITY_COLORS
+            .iter()
+            .map(|(min, color)| format!("{} >{:.0}%", "⣿".color(*color), min * 100.0))
+            .collect();
+        println!("Density relative to the peak: {}", scale.join(" "));
+    }
+    for (k, (id, skymap)) in maps.iter().enumerate() {
+        let mark = if maps.len() > 1 {
+            "⣿".color(EVENT_COLORS[k % EVENT_COLORS.len()]).to_string()
+        } else {
+            "⣿".to_string()
+        };
+        println!(
+            "{} {}: {:.0} deg² (50%), {:.0} deg² (90%), most probable at {}",
+            mark,
+            id.green(),
+            skymap.credible_area_deg2(CREDIBLE_LEVELS[0]),
+            skymap.credible_area_deg2(CREDIBLE_LEVELS[1]),
+            skymap.max_probability()
+        );
+    }
+}
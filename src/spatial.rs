@@ -0,0 +1,363 @@
+// Positions the voices around the listener. Directions are azimuths in
+// degrees from north through east, the way HorizontalPosition gives them, so
+// that on a speaker ring aligned with the compass every event comes from
+// where it is in the sky. Only Ambisonics also uses the elevation.
+
+use std::f64::consts::FRAC_PI_4;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use rodio::cpal::traits::HostTrait;
+use rodio::cpal::{self, SampleRate};
+use rodio::{DeviceTrait, OutputStream, OutputStreamHandle, Source, StreamError};
+use serde::Deserialize;
+
+use crate::ambisonics;
+use crate::datafetch::GWEvent;
+use crate::detectors::{self, Detector};
+use crate::observer::Site;
+
+// Listening to stereo or Ambisonics, we face south, where the sky turns from east (left)
+// to west (right) in the northern hemisphere
+const STEREO_FRONT: f64 = 180.0;
+
+const SAMPLE_RATE: SampleRate = SampleRate(44_100);
+
+// Gains glide to a new direction over this many frames instead of jumping
+const GLIDE_FRAMES: f32 = 4410.0;
+
+// How often the rendering thread looks for a new direction
+const REFRESH_FRAMES: usize = 256;
+
+/// Speaker ring of an installation, read from a JSON file like
+/// `{"speakers": [0, 45, 90, 135, 180, 225, 270, 315]}`. Every entry is the
+/// compass direction of a speaker seen from the center of the room, in the
+/// order of the output channels.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RingLayout {
+    pub speakers: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Layout {
+    Stereo,
+    Ring(RingLayout),
+    /// B-format of the given order for a decoder at the venue
+    Ambisonics(u8),
+}
+
+/// Direction of a sound in degrees, azimuth from north through east.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Direction {
+    pub az: f64,
+    pub el: f64,
+}
+
+impl Layout {
+    pub fn read(path: &Path) -> Result<Layout, Box<dyn std::error::Error>> {
+        let ring: RingLayout = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if ring.speakers.len() < 2 {
+            return Err(format!("{:?} needs at least two speakers", path).into());
+        }
+        Ok(Layout::Ring(ring))
+    }
+
+    pub fn channels(&self) -> u16 {
+        match self {
+            Layout::Stereo => 2,
+            Layout::Ring(ring) => ring.speakers.len() as u16,
+            Layout::Ambisonics(order) => ambisonics::channels(*order),
+        }
+    }
+
+    /// Gain of every channel for a sound from `direction`, or from
+    /// everywhere alike for `None`. Speaker gains always add up to unit power.
+    pub fn gains(&self, direction: Option<Direction>) -> Vec<f32> {
+        let n = self.channels() as usize;
+        let Some(direction) = direction else {
+            return match self {
+                Layout::Ambisonics(order) => ambisonics::diffuse(*order),
+                _ => vec![(1.0 / n as f32).sqrt(); n],
+            };
+        };
+        match self {
+            Layout::Stereo => {
+                // -1 is left, 1 right, equal power in between
+                let pan = (direction.az - STEREO_FRONT).to_radians().sin();
+                let angle = (pan + 1.0) * FRAC_PI_4;
+                vec![angle.cos() as f32, angle.sin() as f32]
+            }
+            Layout::Ring(ring) => vbap(&ring.speakers, direction.az),
+            Layout::Ambisonics(order) => {
+                // Ambisonic azimuths count to the left of the front
+                ambisonics::encode(*order, STEREO_FRONT - direction.az, direction.el)
+            }
+        }
+    }
+}
+
+fn unit(azimuth: f64) -> (f64, f64) {
+    let a = azimuth.to_radians();
+    (a.cos(), a.sin())
+}
+
+/// Two dimensional vector base amplitude panning (Pulkki 1997) between the
+/// two speakers next to `azimuth`.
+fn vbap(speakers: &[f64], azimuth: f64) -> Vec<f32> {
+    let mut gains = vec![0.0; speakers.len()];
+
+    // Speakers sorted around the circle, each paired with the next one
+    let mut order: Vec<usize> = (0..speakers.len()).collect();
+    order.sort_by(|a, b| speakers[*a].rem_euclid(360.0).total_cmp(&speakers[*b].rem_euclid(360.0)));
+    let offset = |i: usize| (speakers[i] - speakers[order[0]]).rem_euclid(360.0);
+    let target = (azimuth - speakers[order[0]]).rem_euclid(360.0);
+    let k = order.iter().rposition(|i| offset(*i) <= target).unwrap_or(0);
+    let (a, b) = (order[k], order[(k + 1) % order.len()]);
+
+    // Solve p = g_a l_a + g_b l_b
+    let (p, la, lb) = (unit(azimuth), unit(speakers[a]), unit(speakers[b]));
+    let det = la.0 * lb.1 - la.1 * lb.0;
+    let (ga, gb) = if det.abs() < 1e-9 {
+        // Both speakers in one direction, or opposite each other
+        (1.0, 0.0)
+    } else {
+        (((p.0 * lb.1 - p.1 * lb.0) / det).max(0.0), ((la.0 * p.1 - la.1 * p.0) / det).max(0.0))
+    };
+    let norm = (ga * ga + gb * gb).sqrt().max(f64::EPSILON);
+    gains[a] += (ga / norm) as f32;
+    gains[b] += (gb / norm) as f32;
+    gains
+}
+
+/// Opens the default output device with one channel per speaker. Falls back
+/// to the device's default channels, which drops or repeats speakers.
+pub fn open_output(channels: u16) -> Result<(OutputStream, OutputStreamHandle), StreamError> {
+    if channels == 2 {
+        return OutputStream::try_default();
+    }
+    let device = cpal::default_host().default_output_device().ok_or(StreamError::NoDevice)?;
+    let config = device.supported_output_configs().ok().and_then(|mut configs| {
+        configs.find(|c| {
+            c.channels() == channels
+                && (c.min_sample_rate()..=c.max_sample_rate()).contains(&SAMPLE_RATE)
+        })
+    });
+    match config {
+        Some(config) => {
+            OutputStream::try_from_device_config(&device, config.with_sample_rate(SAMPLE_RATE))
+        }
+        None => {
+            println!("The output device has no {} channel mode. Using its default.", channels);
+            OutputStream::try_from_device(&device)
+        }
+    }
+}
+
+/// Where an event should sound from: its position in the sky of `site`, or
+/// the mean bearing of its detectors without a skymap. Without a site there
+/// is no sky to place it in, so it sounds from everywhere alike.
+pub fn event_direction(event: &GWEvent, site: Option<&Site>) -> Option<Direction> {
+    let site = site?;
+    if let Some(horizontal) = event.horizontal(site, Utc::now()) {
+        return Some(Direction { az: horizontal.az, el: horizontal.alt });
+    }
+
+    let (x, y) = event
+        .detectors
+        .iter()
+        .filter_map(|name| detectors::find(name))
+        .filter_map(|detector| detector_direction(detector, Some(site)))
+        .map(|direction| unit(direction.az))
+        .fold((0.0, 0.0), |(x, y), (dx, dy)| (x + dx, y + dy));
+    (x.hypot(y) > 1e-9)
+        .then(|| Direction { az: y.atan2(x).to_degrees().rem_euclid(360.0), el: 0.0 })
+}
+
+/// Where a detector lies on the horizon: the bearing of the great circle to
+/// it from `site`. Like the events, it sounds from everywhere alike without a
+/// site.
+pub fn detector_direction(detector: &Detector, site: Option<&Site>) -> Option<Direction> {
+    let site = site?;
+    let (lat1, lat2) = (site.lat.to_radians(), detector.lat.to_radians());
+    let dlon = (detector.lon - site.lon).to_radians();
+    let az = (dlon.sin() * lat2.cos())
+        .atan2(lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos());
+    Some(Direction { az: az.to_degrees().rem_euclid(360.0), el: 0.0 })
+}
+
+/// Handle to move the voices rendered through it while they play.
+#[derive(Clone)]
+pub struct Panner {
+    layout: Arc<Layout>,
+    target: Arc<Mutex<Vec<f32>>>,
+}
+
+impl Panner {
+    /// Starts out sounding from everywhere alike.
+    pub fn new(layout: &Arc<Layout>) -> Self {
+        Panner { layout: layout.clone(), target: Arc::new(Mutex::new(layout.gains(None))) }
+    }
+
+    pub fn set_direction(&self, direction: Option<Direction>) {
+        *self.target.lock().unwrap() = self.layout.gains(direction);
+    }
+
+    /// Renders `input` on all channels of the layout, mixed down to mono first.
+    pub fn spatialize<S>(&self, input: S) -> Spatialized<S>
+    where
+        S: Source<Item = f32>,
+    {
+        let gains = self.target.lock().unwrap().clone();
+        Spatialized {
+            input,
+            channels: self.layout.channels(),
+            frame: vec![0.0; gains.len()],
+            gains: gains.clone(),
+            target: self.target.clone(),
+            position: usize::MAX,
+            frames_until_refresh: 0,
+            target_cache: gains.clone(),
+        }
+    }
+}
+
+pub struct Spatialized<S> {
+    input: S,
+    channels: u16,
+    gains: Vec<f32>,
+    target: Arc<Mutex<Vec<f32>>>,
+    target_cache: Vec<f32>,
+    frames_until_refresh: usize,
+    // The output frame being handed out and the position in it
+    frame: Vec<f32>,
+    position: usize,
+}
+
+impl<S> Spatialized<S>
+where
+    S: Source<Item = f32>,
+{
+    fn next_frame(&mut self) -> Option<()> {
+        // The queue feeding us may change channels between sounds, but only
+        // on frame boundaries
+        let input_channels = self.input.channels().max(1);
+        let mut sum = self.input.next()?;
+        for _ in 1..input_channels {
+            sum += self.input.next().unwrap_or(0.0);
+        }
+        let mono = sum / input_channels as f32;
+
+        if self.frames_until_refresh == 0 {
+            self.target_cache.clone_from(&self.target.lock().unwrap());
+            self.frames_until_refresh = REFRESH_FRAMES;
+        }
+        self.frames_until_refresh -= 1;
+
+        for ((gain, target), out) in
+            self.gains.iter_mut().zip(&self.target_cache).zip(self.frame.iter_mut())
+        {
+            *gain += (target - *gain) / GLIDE_FRAMES;
+            *out = mono * *gain;
+        }
+        self.position = 0;
+        Some(())
+    }
+}
+
+impl<S> Iterator for Spatialized<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position >= self.frame.len() {
+            self.next_frame()?;
+        }
+        let sample = self.frame[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Spatialized<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        // Our frame ends where the input's does. The mixer drops sources
+        // that claim empty frames, so partial input frames count whole.
+        let left_in_frame = self.frame.len().saturating_sub(self.position);
+        let input_channels = self.input.channels().max(1) as usize;
+        self.input
+            .current_frame_len()
+            .map(|len| len.div_ceil(input_channels).max(1) * self.channels as usize + left_in_frame)
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detector_bearings() {
+        let hanford = detectors::find("H1").unwrap();
+        assert_eq!(detector_direction(hanford, None), None);
+        // Hanford lies northwest of Livingston
+        let livingston = Site { lat: 30.562_894, lon: -90.774_242 };
+        let direction = detector_direction(hanford, Some(&livingston)).unwrap();
+        assert!((direction.az - 313.8).abs() < 0.1, "{:?}", direction);
+        assert_eq!(direction.el, 0.0);
+    }
+
+    fn assert_gains(gains: &[f32], expected: &[f32]) {
+        assert_eq!(gains.len(), expected.len());
+        for (gain, expected) in gains.iter().zip(expected) {
+            assert!((gain - expected).abs() < 1e-6, "{:?} instead of {:?}", gains, expected);
+        }
+    }
+
+    #[test]
+    fn vbap_on_a_speaker() {
+        let speakers = [0.0, 90.0, 180.0, 270.0];
+        assert_gains(&vbap(&speakers, 90.0), &[0.0, 1.0, 0.0, 0.0]);
+        assert_gains(&vbap(&speakers, 0.0), &[1.0, 0.0, 0.0, 0.0]);
+        assert_gains(&vbap(&speakers, 360.0), &[1.0, 0.0, 0.0, 0.0]);
+        // In any channel order
+        assert_gains(&vbap(&[180.0, 270.0, 0.0, 90.0], 270.0), &[0.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn vbap_keeps_power_between_speakers() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let speakers = [0.0, 90.0, 180.0, 270.0];
+        assert_gains(&vbap(&speakers, 45.0), &[half, half, 0.0, 0.0]);
+        // Across north
+        assert_gains(&vbap(&speakers, 315.0), &[half, 0.0, 0.0, half]);
+        assert_gains(&vbap(&speakers, -45.0), &[half, 0.0, 0.0, half]);
+
+        let speakers = [30.0, 110.0, 250.0, 330.0];
+        for azimuth in (0..360).step_by(5) {
+            let gains = vbap(&speakers, azimuth as f64);
+            let power: f32 = gains.iter().map(|g| g * g).sum();
+            assert!((power - 1.0).abs() < 1e-6, "{:?} at {}", gains, azimuth);
+            // Only the two speakers next to the sound play
+            let playing = gains.iter().filter(|g| **g > 0.0).count();
+            assert!((1..=2).contains(&playing), "{:?} at {}", gains, azimuth);
+        }
+    }
+}
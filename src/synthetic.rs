@@ -0,0 +1,383 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::json;
+
+use crate::cache;
+use crate::datafetch::{self, FitsParams, GWEventVec, GraceDbEvent};
+use crate::healpix;
+use crate::skymap::SkyPosition;
+
+// Detector networks and how often they are picked
+const NETWORKS: [(&[&str], f64); 7] = [
+    (&["H1", "L1"], 0.35),
+    (&["H1", "L1", "V1"], 0.3),
+    (&["L1", "V1"], 0.1),
+    (&["H1", "V1"], 0.1),
+    (&["H1", "L1", "K1"], 0.05),
+    (&["H1"], 0.05),
+    (&["L1"], 0.05),
+];
+
+// BBH, BNS, NSBH, Terrestrial: how often each is the most likely class and
+// the horizon distance in Mpc
+const CLASSES: [(f64, f64); 4] = [(0.6, 3000.0), (0.1, 250.0), (0.1, 600.0), (0.2, 1500.0)];
+
+// Anything rarer than once per month would raise a significant alert
+const SIGNIFICANT_FAR: f64 = 1.0 / (30.0 * 24.0 * 3600.0);
+
+// The newest event is a little older than this, so that a seed gives the
+// same events on every run. 2025-01-01T00:00:00Z
+const EPOCH: i64 = 1_735_689_600;
+
+const BASE_ORDER: u8 = 3;
+const MAX_ORDER: u8 = 8;
+
+/// Letters GraceDB appends to the date of the `n`th superevent of a day:
+/// a to z, then aa, ab and so on.
+fn id_suffix(n: u32) -> String {
+    let mut suffix = Vec::new();
+    let mut n = n + 1;
+    while n > 0 {
+        n -= 1;
+        suffix.push(b'a' + (n % 26) as u8);
+        n /= 26;
+    }
+    suffix.iter().rev().map(|c| char::from(*c)).collect()
+}
+
+/// Seeded source of believable fake events, for rehearsals without network.
+pub struct SyntheticEvents {
+    rng: StdRng,
+    time: DateTime<Utc>,
+    // Day of the last event and how many events it has so far
+    day: Option<(NaiveDate, u32)>,
+}
+
+struct Skymap {
+    instruments: Vec<String>,
+    dist_mean: f64,
+    dist_std: f64,
+    // Most probable direction (colatitude, longitude) and spread, in radians
+    theta: f64,
+    phi: f64,
+    sigma: f64,
+}
+
+impl SyntheticEvents {
+    pub fn new(seed: u64) -> Self {
+        let time = Utc.timestamp_opt(EPOCH, 0).single().unwrap_or_default();
+        SyntheticEvents { rng: StdRng::seed_from_u64(seed), time, day: None }
+    }
+
+    fn pick<T: Copy>(&mut self, choices: &[(T, f64)]) -> T {
+        let total: f64 = choices.iter().map(|(_, w)| w).sum();
+        let mut x = self.rng.gen_range(0.0..total);
+        for (choice, weight) in choices {
+            if x < *weight {
+                return *choice;
+            }
+            x -= weight;
+        }
+        choices[choices.len() - 1].0
+    }
+
+    /// Probabilities summing to one, with a clear favourite.
+    fn classification(&mut self, favourite: usize) -> [f64; 4] {
+        let mut weights = [0.0; 4];
+        for (i, w) in weights.iter_mut().enumerate() {
+            // Exponentially distributed, i.e. a flat Dirichlet
+            *w = -self.rng.gen_range(f64::EPSILON..1.0).ln();
+            if i == favourite {
+                *w *= 10.0;
+            }
+        }
+        let total: f64 = weights.iter().sum();
+        weights.map(|w| w / total)
+    }
+
+    /// Produces the update.json contents GraceDB would send for the next
+    /// (older) event, and the parameters for its skymap.
+    fn next_event(&mut self) -> (serde_json::Value, Skymap) {
+        self.time -=
+            Duration::try_seconds(self.rng.gen_range(3600..10 * 24 * 3600)).unwrap_or_default();
+        let date = self.time.date_naive();
+        let count = match self.day {
+            Some((day, count)) if day == date => count + 1,
+            _ => 0,
+        };
+        self.day = Some((date, count));
+        // Counted from the newest event of the day, as they are made
+        let id = format!("MS{}{}", self.time.format("%y%m%d"), id_suffix(count));
+
+        let classes: Vec<(usize, f64)> = CLASSES.iter().map(|(w, _)| *w).enumerate().collect();
+        let favourite = self.pick(&classes);
+        let [bbh, bns, nsbh, terrestrial] = self.classification(favourite);
+
+        // Uniform in volume
+        let horizon = CLASSES[favourite].1;
+        let dist_mean = horizon * self.rng.gen_range(0.001f64..1.0).cbrt();
+        let dist_std = dist_mean * self.rng.gen_range(0.15..0.4);
+
+        let instruments: Vec<String> = self.pick(&NETWORKS).iter().map(|x| x.to_string()).collect();
+        // More detectors, better localisation
+        let sigma_deg: f64 = match instruments.len() {
+            1 => self.rng.gen_range(40.0..80.0),
+            2 => self.rng.gen_range(8.0..30.0),
+            _ => self.rng.gen_range(2.0..10.0),
+        };
+
+        let far = 10f64.powf(self.rng.gen_range(-15.0..-6.0));
+        let has_ns = (bns + nsbh + self.rng.gen_range(0.0..0.05)).min(1.0);
+
+        let format = "%+";
+        let update = json!({
+            "superevent_id": id,
+            "alert_type": "UPDATE",
+            "time_created": (self.time + Duration::try_minutes(5).unwrap_or_default()).format(format).to_string(),
+            "event": {
+                "significant": far < SIGNIFICANT_FAR,
+                "time": self.time.format(format).to_string(),
+                "far": far,
+                "instruments": instruments,
+                "group": "CBC",
+                "pipeline": "gstlal",
+                "search": "AllSky",
+                "properties": {
+                    "HasNS": has_ns,
+                    "HasRemnant": (bns + 0.5 * nsbh).min(has_ns),
+                    "HasMassGap": self.rng.gen_range(0.0..0.1),
+                },
+                "classification": {
+                    "BBH": bbh,
+                    "BNS": bns,
+                    "NSBH": nsbh,
+                    "Terrestrial": terrestrial,
+                },
+            },
+        });
+
+        let skymap = Skymap {
+            instruments,
+            dist_mean,
+            dist_std,
+            theta: self.rng.gen_range(-1.0f64..1.0).acos(),
+            phi: self.rng.gen_range(0.0..2.0 * PI),
+            sigma: sigma_deg.to_radians(),
+        };
+
+        (update, skymap)
+    }
+}
+
+/// Generates `n` events, newest first like GraceDB lists them, and passes them
+/// through the same conversion as downloaded events. With `skymaps`, a small
+/// multiorder FITS file is written to `cache_dir` for each of them.
+pub fn synthetic_events(
+    seed: u64,
+    n: usize,
+    skymaps: bool,
+    cache_dir: &Path,
+) -> Result<GWEventVec, Box<dyn std::error::Error>> {
+    let mut generator = SyntheticEvents::new(seed);
+    let mut result: GWEventVec = Vec::new();
+
+    for _ in 0..n {
+        let (update, skymap) = generator.next_event();
+        let id = update["superevent_id"].as_str().unwrap_or_default().to_string();
+        let eventdata: GraceDbEvent = serde_json::from_value(update)?;
+
+        let fits_data = if skymaps {
+            let file_path = datafetch::skymap_path(cache_dir, &id);
+            write_skymap(&file_path, &skymap)?;
+            Some(datafetch::read_fits(&file_path)?)
+        } else {
+            // For a von Mises-Fisher density, 1 - cos(r) of the radius r
+            // enclosing 90% is ln(10) / kappa
+            let area_sr = 2.0 * PI * 10f64.ln() * skymap.sigma * skymap.sigma;
+            Some(FitsParams {
+                dist_mean: skymap.dist_mean,
+                dist_std: skymap.dist_std,
+                instruments: skymap.instruments,
+                area_90_deg2: Some(area_sr * (180.0 / PI) * (180.0 / PI)),
+                max_probability: Some(SkyPosition::from_ang(skymap.theta, skymap.phi)),
+            })
+        };
+
+        result.push(datafetch::gracedb_to_gwevent(eventdata, fits_data));
+    }
+
+    Ok(result)
+}
+
+fn angular_distance(theta1: f64, phi1: f64, theta2: f64, phi2: f64) -> f64 {
+    let cos = theta1.cos() * theta2.cos() + theta1.sin() * theta2.sin() * (phi1 - phi2).cos();
+    cos.clamp(-1.0, 1.0).acos()
+}
+
+// Refines the pixels close to the peak until they are finer than the spread
+fn multiorder_pixels(skymap: &Skymap) -> Vec<(u8, u64)> {
+    let mut pixels: Vec<(u8, u64)> =
+        (0..healpix::npix(BASE_ORDER)).map(|i| (BASE_ORDER, i)).collect();
+    loop {
+        let mut refined = false;
+        let mut next = Vec::with_capacity(pixels.len());
+        for (order, ipix) in pixels {
+            let (theta, phi) = healpix::nest2ang(order, ipix);
+            let size = healpix::pixel_area(order).sqrt();
+            let distance = angular_distance(theta, phi, skymap.theta, skymap.phi);
+            if order < MAX_ORDER
+                && size > skymap.sigma / 2.0
+                && distance < 3.0 * skymap.sigma + size
+            {
+                next.extend((0..4).map(|child| (order + 1, 4 * ipix + child)));
+                refined = true;
+            } else {
+                next.push((order, ipix));
+            }
+        }
+        pixels = next;
+        if !refined {
+            return pixels;
+        }
+    }
+}
+
+fn card(key: &str, value: &str) -> String {
+    format!("{:<80}", format!("{:<8}= {:>20}", key, value))
+}
+
+fn string_card(key: &str, value: &str) -> String {
+    format!("{:<80}", format!("{:<8}= '{:<8}'", key, value))
+}
+
+fn write_block(out: &mut Vec<u8>, cards: &[String]) {
+    for card in cards {
+        out.extend_from_slice(card.as_bytes());
+    }
+    out.extend_from_slice(format!("{:<80}", "END").as_bytes());
+    let padded = out.len().div_ceil(2880) * 2880;
+    out.resize(padded, b' ');
+}
+
+/// Writes a BAYESTAR style multiorder skymap with a von Mises-Fisher shaped
+/// probability density.
+fn write_skymap(path: &Path, skymap: &Skymap) -> Result<(), Box<dyn std::error::Error>> {
+    let pixels = multiorder_pixels(skymap);
+
+    let kappa = 1.0 / (skymap.sigma * skymap.sigma);
+    let weights: Vec<f64> = pixels
+        .iter()
+        .map(|(order, ipix)| {
+            let (theta, phi) = healpix::nest2ang(*order, *ipix);
+            let distance = angular_distance(theta, phi, skymap.theta, skymap.phi);
+            (kappa * (distance.cos() - 1.0)).exp()
+        })
+        .collect();
+    let total: f64 = pixels
+        .iter()
+        .zip(weights.iter())
+        .map(|((order, _), w)| w * healpix::pixel_area(*order))
+        .sum();
+
+    let mu = skymap.dist_mean;
+    let sigma = skymap.dist_std;
+    let norm = 1.0 / (mu * mu + sigma * sigma);
+
+    let mut out: Vec<u8> = Vec::new();
+    write_block(
+        &mut out,
+        &[card("SIMPLE", "T"), card("BITPIX", "8"), card("NAXIS", "0"), card("EXTEND", "T")],
+    );
+
+    let columns = [
+        ("UNIQ", "K"),
+        ("PROBDENSITY", "D"),
+        ("DISTMU", "D"),
+        ("DISTSIGMA", "D"),
+        ("DISTNORM", "D"),
+    ];
+    let mut cards = vec![
+        string_card("XTENSION", "BINTABLE"),
+        card("BITPIX", "8"),
+        card("NAXIS", "2"),
+        card("NAXIS1", &(8 * columns.len()).to_string()),
+        card("NAXIS2", &pixels.len().to_string()),
+        card("PCOUNT", "0"),
+        card("GCOUNT", "1"),
+        card("TFIELDS", &columns.len().to_string()),
+    ];
+    for (i, (name, format)) in columns.iter().enumerate() {
+        cards.push(string_card(&format!("TTYPE{}", i + 1), name));
+        cards.push(string_card(&format!("TFORM{}", i + 1), format));
+    }
+    cards.extend([
+        string_card("PIXTYPE", "HEALPIX"),
+        string_card("ORDERING", "NUNIQ"),
+        string_card("COORDSYS", "C"),
+        card("MOCORDER", &MAX_ORDER.to_string()),
+        string_card("INSTRUME", &skymap.instruments.join(",")),
+        card("DISTMEAN", &format!("{:?}", mu)),
+        card("DISTSTD", &format!("{:?}", sigma)),
+    ]);
+    write_block(&mut out, &cards);
+
+    let data_start = out.len();
+    for ((order, ipix), weight) in pixels.iter().zip(weights) {
+        out.extend_from_slice(&healpix::uniq(*order, *ipix).to_be_bytes());
+        for value in [weight / total, mu, sigma, norm] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    let padded = data_start + (out.len() - data_start).div_ceil(2880) * 2880;
+    out.resize(padded, 0);
+
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir); // ignore if cache already exists
+    }
+    cache::write_atomically(path, &out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_events() {
+        let dir = Path::new("unused");
+        let first = synthetic_events(7, 5, false, dir).unwrap();
+        let second = synthetic_events(7, 5, false, dir).unwrap();
+        assert_eq!(serde_json::to_string(&first).unwrap(), serde_json::to_string(&second).unwrap());
+
+        let other = synthetic_events(8, 5, false, dir).unwrap();
+        assert_ne!(first[0].id, other[0].id);
+    }
+
+    #[test]
+    fn id_suffixes() {
+        let suffixes: Vec<String> = [0, 1, 25, 26, 27, 701, 702].map(id_suffix).to_vec();
+        assert_eq!(suffixes, ["a", "b", "z", "aa", "ab", "zz", "aaa"]);
+    }
+
+    #[test]
+    fn ids_are_unique() {
+        // Some days have several of these
+        let events = synthetic_events(3, 200, false, Path::new("unused")).unwrap();
+        let mut ids: Vec<&str> = events.iter().map(|ev| ev.id.as_str()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), events.len());
+    }
+
+    #[test]
+    fn events_are_older_than_the_epoch() {
+        let events = synthetic_events(0, 10, false, Path::new("unused")).unwrap();
+        assert!(events.windows(2).all(|pair| pair[0].time > pair[1].time));
+        assert!(events[0].time.timestamp() < EPOCH);
+    }
+}
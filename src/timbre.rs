@@ -0,0 +1,114 @@
+// What an event most likely was, heard in the masses M1/M2: their sine beats
+// stand for binary black holes, triangles beating at the same pitch for
+// binary neutron stars and noise for a terrestrial origin. An event of mixed
+// classification crossfades the three.
+
+use rodio::source::Source;
+
+use crate::datafetch::GWEvent;
+use crate::noise::Noise;
+use crate::triangle_wave::TriangleWave;
+
+/// Pitch, beat and level of the sine beats of a mass voice, shared by the
+/// generated tones and the triangles crossfaded into either.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MassVoice {
+    /// Hz
+    pub freq: f32,
+    /// Seconds from one beat to the next
+    pub beat_length: f32,
+    /// Amplitude matching the recorded samples
+    pub amplitude: f32,
+}
+
+pub const M1: MassVoice = MassVoice { freq: 140.0, beat_length: 4.98, amplitude: 0.07 };
+pub const M2: MassVoice = MassVoice { freq: 130.0, beat_length: 9.96, amplitude: 0.08 };
+
+/// Share of each timbre family in the voice of an event, summing to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Classes {
+    /// Sine beats, binary black holes
+    pub bbh: f32,
+    /// Triangles, binary neutron stars
+    pub bns: f32,
+    /// Noise, terrestrial origin
+    pub terrestrial: f32,
+}
+
+impl Default for Classes {
+    /// The masses as they are, sine beats only.
+    fn default() -> Self {
+        Classes { bbh: 1.0, bns: 0.0, terrestrial: 0.0 }
+    }
+}
+
+impl Classes {
+    /// Classes of `event`. Half of NSBH goes to either family, events
+    /// without a classification keep the sine beats.
+    pub fn of(event: &GWEvent) -> Self {
+        let bbh = event.bh_bh + event.ns_bh / 2.0;
+        let bns = event.ns_ns + event.ns_bh / 2.0;
+        let total = bbh + bns + event.terrestrial;
+        if total <= 0.0 {
+            return Classes::default();
+        }
+        Classes {
+            bbh: (bbh / total) as f32,
+            bns: (bns / total) as f32,
+            terrestrial: (event.terrestrial / total) as f32,
+        }
+    }
+
+    /// `beat`, the sine beats of `voice`, crossfaded with triangles and noise
+    /// as loud.
+    pub fn apply<S>(&self, beat: S, voice: &MassVoice) -> impl Source<Item = f32> + Clone
+    where
+        S: Source<Item = f32> + Clone,
+    {
+        let MassVoice { freq, beat_length, amplitude } = *voice;
+        // The families are unrelated, so the shares are of power
+        let [sine, triangle, noise] = [self.bbh, self.bns, self.terrestrial].map(f32::sqrt);
+        // Same split as SineBeat, the beat ignores phase
+        let offset = 1.0 / beat_length / 2.0;
+        let triangles = TriangleWave::new(freq + offset)
+            .mix(TriangleWave::new(freq - offset))
+            .amplify(0.5 * amplitude * triangle);
+        beat.amplify(sine)
+            .mix(triangles)
+            .mix(Noise::new(freq.to_bits().into()).amplify(amplitude * noise))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn event(bh_bh: f64, ns_bh: f64, ns_ns: f64, terrestrial: f64) -> GWEvent {
+        GWEvent { bh_bh, ns_bh, ns_ns, terrestrial, ..GWEvent::blank("S1", Utc::now()) }
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn shares_of_the_classification() {
+        let classes = Classes::of(&event(0.6, 0.0, 0.0, 0.4));
+        assert_close(classes.bbh, 0.6);
+        assert_close(classes.bns, 0.0);
+        assert_close(classes.terrestrial, 0.4);
+
+        // Half of NSBH to either family, normalized
+        let classes = Classes::of(&event(0.2, 0.4, 0.2, 0.0));
+        assert_close(classes.bbh, 0.5);
+        assert_close(classes.bns, 0.5);
+    }
+
+    #[test]
+    fn unclassified_events_keep_the_sine_beats() {
+        assert_eq!(Classes::of(&event(0.0, 0.0, 0.0, 0.0)), Classes::default());
+        assert_eq!(Classes::default(), Classes { bbh: 1.0, bns: 0.0, terrestrial: 0.0 });
+    }
+}
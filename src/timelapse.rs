@@ -0,0 +1,73 @@
+use std::ops::ControlFlow;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::datafetch::GWEvent;
+
+/// Compresses the real arrival times of `events` by `speedup` into offsets
+/// relative to the earliest event. Returns the events in order of arrival.
+///
+/// A speedup of 43200 plays one 30 day month of an observing run per minute.
+pub fn schedule(events: &[GWEvent], speedup: f64) -> Vec<(Duration, &GWEvent)> {
+    let mut sorted: Vec<&GWEvent> = events.iter().collect();
+    sorted.sort_by_key(|ev| ev.time);
+
+    let Some(first) = sorted.first().map(|ev| ev.time) else {
+        return Vec::new();
+    };
+
+    sorted
+        .into_iter()
+        .map(|ev| {
+            let real_secs = (ev.time - first).num_milliseconds() as f64 / 1000.0;
+            // Slowed down beyond what a Duration holds, it never arrives
+            let offset = Duration::try_from_secs_f64(real_secs / speedup).unwrap_or(Duration::MAX);
+            (offset, ev)
+        })
+        .collect()
+}
+
+/// Blocks while stepping through the schedule, calling `cue` at every arrival
+/// until it breaks.
+pub fn play<F>(arrivals: &[(Duration, &GWEvent)], mut cue: F)
+where
+    F: FnMut(&GWEvent) -> ControlFlow<()>,
+{
+    let start = Instant::now();
+    for (offset, event) in arrivals {
+        if let Some(wait) = offset.checked_sub(start.elapsed()) {
+            thread::sleep(wait);
+        }
+        if cue(event).is_break() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    fn event(id: &str, unix: i64) -> GWEvent {
+        GWEvent::blank(id, Utc.timestamp_opt(unix, 0).unwrap())
+    }
+
+    #[test]
+    fn arrivals_in_order_and_sped_up() {
+        let events = [event("S3", 86_400), event("S1", 0), event("S2", 3_600)];
+        let arrivals = schedule(&events, 3600.0);
+        let ids: Vec<&str> = arrivals.iter().map(|(_, ev)| ev.id.as_str()).collect();
+        assert_eq!(ids, ["S1", "S2", "S3"]);
+        let offsets: Vec<Duration> = arrivals.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, [Duration::ZERO, Duration::from_secs(1), Duration::from_secs(24)]);
+        assert!(schedule(&[], 1.0).is_empty());
+    }
+
+    #[test]
+    fn tiny_speedups_saturate() {
+        let events = [event("S1", 0), event("S2", 60)];
+        assert_eq!(schedule(&events, 1e-300)[1].0, Duration::MAX);
+    }
+}
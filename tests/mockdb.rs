@@ -0,0 +1,114 @@
+// Fetches the fixtures in tests/fixtures/gracedb from gwrust-mockdb the way
+// gwrust fetches from GraceDB, and replays what it recorded.
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/gracedb");
+
+struct MockDb {
+    child: Child,
+    url: String,
+}
+
+impl MockDb {
+    fn start() -> MockDb {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_gwrust-mockdb"))
+            .args([FIXTURES, "--port", "0"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // Serving "…" on http://127.0.0.1:<port>/apiweb/
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        let url = line.split_whitespace().last().unwrap().to_string();
+        // It logs every request
+        thread::spawn(move || for _ in stdout.lines() {});
+        MockDb { child, url }
+    }
+}
+
+impl Drop for MockDb {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gwrust-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn gwrust(args: &[&str], cache_dir: &Path, command: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_gwrust"))
+        .args(["--cache-dir", cache_dir.to_str().unwrap(), "--http-min-interval", "0"])
+        .args(args)
+        .args(command)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn fetch_from_mockdb() {
+    let mockdb = MockDb::start();
+    let cache_dir = temp_dir("fetch");
+
+    let output = gwrust(&["--gracedb-url", &mockdb.url], &cache_dir, &["events", "fetch"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+
+    // Two pages, one event with a broken update.json
+    assert!(stdout.contains("Event: id=MS261018d"), "{}", stdout);
+    assert!(stdout.contains("could not fetch"), "{}", stdout);
+    assert!(!stdout.contains("Event: id=MS261014x"), "{}", stdout);
+    assert!(cache_dir.join("Events.json").is_file());
+    assert!(cache_dir.join("MS261018d-bayestar.multiorder.fits").is_file());
+    // Distance from the skymap headers
+    assert!(stdout.contains("dist_mean: 2718.186462171018"), "{}", stdout);
+
+    // Showing needs nothing but the cache
+    drop(mockdb);
+    let offline = ["--gracedb-url", "http://127.0.0.1:9/apiweb/"];
+    let output = gwrust(&offline, &cache_dir, &["events", "show", "MS261018d"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(!stdout.contains("Renewing"), "{}", stdout);
+
+    let _ = fs::remove_dir_all(&cache_dir);
+}
+
+#[test]
+fn replay_recorded_requests() {
+    let recording = temp_dir("recording");
+    {
+        let mockdb = MockDb::start();
+        let cache_dir = temp_dir("record");
+        let record = ["--gracedb-url", &mockdb.url, "--record-http", recording.to_str().unwrap()];
+        let output = gwrust(&record, &cache_dir, &["events", "fetch"]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+        let _ = fs::remove_dir_all(&cache_dir);
+    }
+
+    // The server is gone, everything has to come from the recording
+    let cache_dir = temp_dir("replay");
+    let replay = [
+        "--gracedb-url",
+        "http://127.0.0.1:9/apiweb/",
+        "--replay-http",
+        recording.to_str().unwrap(),
+    ];
+    let output = gwrust(&replay, &cache_dir, &["events", "fetch"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Event: id=MS261018d"), "{}", stdout);
+
+    let _ = fs::remove_dir_all(&recording);
+    let _ = fs::remove_dir_all(&cache_dir);
+}